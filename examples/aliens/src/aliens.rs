@@ -1,13 +1,16 @@
-use macroquad::{prelude::Rect, audio::{self, Sound}};
-use mengine_quad::{engine::{SpriteExt, SPRITEACTION, SA_ADDSPRITE, Sprite, Resource, BA_DIE}, rand_int, Point, Drawable, rand_uuid};
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::Rect;
+use mengine_quad::{audio::{AudioBackend, AudioManager, QuadBackend, SoundHandle}, engine::{SpriteExt, SPRITEACTION, SA_ADDSPRITE, Sprite, Resource, BA_DIE}, rand_int, Point, Drawable, rand_uuid};
 
 //外星人
-pub struct Timmy {
-    pub sound_missile: Sound,
+pub struct Timmy<B: AudioBackend = QuadBackend> {
+    pub audio: Rc<RefCell<AudioManager<B>>>,
+    pub sound_missile: SoundHandle,
     pub missile: Drawable,
 }
 
-impl SpriteExt for Timmy {
+impl<B: AudioBackend> SpriteExt for Timmy<B> {
     fn update(&mut self, _:&mut Sprite, sprite_action: SPRITEACTION) -> SPRITEACTION {
         //检查精灵是否要发射子弹
         match rand_int(0, 30) {
@@ -26,14 +29,14 @@ impl SpriteExt for Timmy {
         let mut sub_sprite = Sprite::with_bounds_action(
             rand_uuid(),
             String::from("missile"),
-            Resource::Static(self.missile),
+            Resource::Static(self.missile.clone()),
             bounds,
             BA_DIE,
         );
         sub_sprite.set_velocity(velocity.x, velocity.y);
 
         //播放导弹发射声音
-        audio::play_sound_once(self.sound_missile);
+        self.audio.borrow_mut().play(self.sound_missile);
 
         // println!("top={} bottom={}", pos.top(), pos.bottom());
         sub_sprite.set_position(pos.left() + sprite.width() / 2.0, pos.bottom());
@@ -1,6 +1,8 @@
+use std::{cell::RefCell, rc::Rc};
+
 use aliens::Timmy;
-use mengine_quad::{run, engine::{Resource, GameEngine, Sprite, BA_WRAP}, Animation, State, Settings, Event, Drawable, rand_uuid};
-use macroquad::{prelude::*, audio::{load_sound, Sound, self}};
+use mengine_quad::{run, effects::{Effect, EffectChain}, embed, embed_assets, audio::{AudioBackend, AudioManager, QuadBackend, SoundHandle}, manifest::Resources, engine::{Resource, GameEngine, Sprite, BA_WRAP}, State, Flow, Settings, ScaleMode, Event, rand_uuid};
+use macroquad::prelude::*;
 use anyhow::Result;
 
 mod aliens;
@@ -8,25 +10,51 @@ mod aliens;
 pub const CLIENT_WIDTH:f32 = 600.0;
 pub const CLIENT_HEIGHT:f32 = 450.0;
 
-pub struct Game{
-    sound_explode_missile: Sound,
-    texture_sm_explosion: Drawable,
+pub struct Game<B: AudioBackend = QuadBackend>{
+    audio: Rc<RefCell<AudioManager<B>>>,
+    sound_explode_missile: SoundHandle,
+    resources: Resources,
+    effects: EffectChain,
     sprites: Vec<Sprite>,
 }
 
-impl State for Game{
+impl<B: AudioBackend + 'static> Game<B>{
+    fn new(resources: Resources, audio: AudioManager<B>) -> Game<B>{
+        let sound_explode_missile = resources.sound("explode_missile").unwrap();
+        //CRT效果
+        let mut effects = EffectChain::new();
+        effects.push(Effect::scanlines());
+        effects.push(Effect::vignette());
+        Game{ sprites: vec![], audio: Rc::new(RefCell::new(audio)), sound_explode_missile, resources, effects }
+    }
+}
+
+impl<B: AudioBackend + 'static> State for Game<B>{
     fn event(&mut self, _event: Event) {
         
     }
-    fn update(&mut self) {
+    fn update(&mut self) -> Result<Flow> {
+        self.audio.borrow_mut().tick();
         self.update_sprites();
+        Ok(Flow::Continue)
     }
-    fn draw(&mut self){
+    fn draw(&mut self) -> Result<()> {
         self.draw_sprites();
+        Ok(())
+    }
+    fn engine(&mut self) -> Option<&mut dyn GameEngine> {
+        Some(self)
+    }
+    fn effects(&mut self) -> Option<&mut EffectChain> {
+        Some(&mut self.effects)
+    }
+    fn hot_reload(&mut self) {
+        let reload = self.resources.reload();
+        self.apply_reload(&reload);
     }
 }
 
-impl GameEngine for Game{
+impl<B: AudioBackend + 'static> GameEngine for Game<B>{
     fn sprites_mut(&mut self) -> &mut Vec<Sprite> {
         &mut self.sprites
     }
@@ -39,14 +67,12 @@ impl GameEngine for Game{
         //检查是否子弹精灵死亡
         if self.sprites[sprite_dying_id].name() == "missile"{
 
-            audio::play_sound_once(self.sound_explode_missile);
+            self.audio.borrow_mut().play(self.sound_explode_missile);
+            self.effects.push(Effect::flash(Color::new(1., 0.9, 0.6, 0.4), 0.15));
 
             //在子弹位置创建一个小的爆炸精灵
-            let mut frames = vec![];
-            for y in (0..136).step_by(17) {
-                frames.push(Rect::new(0., y as f32, 17., 17.));
-            }
-            let anim = Animation::active(self.texture_sm_explosion, frames, 25.0);
+            let mut anim = self.resources.animation("sm_explosion").unwrap();
+            anim.start();
 
             let mut sprite = Sprite::from_bitmap(
                 rand_uuid(),
@@ -62,6 +88,32 @@ impl GameEngine for Game{
         }
     }
 
+    //外星人的位置是随机的, 在设置随机数种子之后创建, 录制的过程才能重现
+    fn initialize(&mut self) -> bool {
+        let timmy_ext = Timmy{
+            audio: self.audio.clone(),
+            missile: self.resources.drawable("missile").unwrap(),
+            sound_missile: self.resources.sound("missile").unwrap(),
+        };
+        
+        let bounds = Rect::new(0.0, 0.0, CLIENT_WIDTH, 410.0);
+
+        let mut anim = self.resources.animation("timmy").unwrap();
+        anim.start();
+        
+        let mut alien = Sprite::with_bounds_action(
+            rand_uuid(),
+            String::from("timmy"),
+            Resource::Animation(anim),
+            bounds,
+            BA_WRAP,
+        );
+        alien.set_velocity(3., 0.);
+        alien.ext(timmy_ext);
+        self.add_sprite(alien);
+        true
+    }
+
     fn sprite_collision(&mut self, _hitter_id: usize, _hittee_id: usize) -> bool {
         false
     }
@@ -70,48 +122,59 @@ impl GameEngine for Game{
 #[macroquad::main("Aliens")]
 async fn main() -> Result<()> {
     
-    clear_background(WHITE);
-
-    // 加载素材
-    draw_text("Loading...", screen_width()/2., screen_height()/2., 20., BLACK);
-
-    let texture_missile = Drawable::Texture2D(Texture2D::from_image(&load_image("static/TMissile.png").await?));
-    let texture_timmy = Drawable::Texture2D(Texture2D::from_image(&load_image("static/Timmy.png").await?));
-    let texture_sm_explosion = Drawable::Texture2D(Texture2D::from_image(&load_image("static/SmExplosion.png").await?));
-    let sound_missile = load_sound("static/TMissile.ogg").await?;
-    let sound_explode_missile = load_sound("static/SmExplode.ogg").await?;
-    
-    let timmy_ext = Timmy{ missile: texture_missile, sound_missile };
-
-    let mut frames = vec![];
-    for y in (0..136).step_by(17) {
-        frames.push(Rect::new(0., y as f32, 33., 17.));
+    //资源打包进程序, 不需要和static目录一起发布
+    embed::set_embedded(embed_assets![
+        "static/assets.json",
+        "static/TMissile.png",
+        "static/Timmy.png",
+        "static/SmExplosion.png",
+        "static/TMissile.ogg",
+        "static/SmExplode.ogg",
+    ]);
+
+    // 加载素材, 同一声音最多同时播放4次
+    let mut audio = AudioManager::new();
+    #[allow(unused_mut)]
+    let mut resources = Resources::load("static/assets.json", CLIENT_WIDTH, CLIENT_HEIGHT, &mut audio).await?;
+    //调试时修改图片或清单后自动重新加载
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    resources.watch();
+    let mut game = Game::new(resources, audio);
+
+    //--record <文件> 录制输入, --replay <文件> 回放录制的输入
+    let (mut record, mut replay) = (None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = args.next(),
+            "--replay" => replay = args.next(),
+            _ => (),
+        }
     }
     
-    let bounds = Rect::new(0.0, 0.0, CLIENT_WIDTH, 410.0);
-
-    let mut anim =
-        Animation::active(texture_timmy, frames, 25.0);
-    anim.set_repeat(true);
-    
-    let mut alien = Sprite::with_bounds_action(
-        rand_uuid(),
-        String::from("timmy"),
-        Resource::Animation(anim),
-        bounds,
-        BA_WRAP,
-    );
-    alien.set_velocity(3., 0.);
-    alien.ext(timmy_ext);
-    
-    let mut game = Game{ sprites: vec![alien], sound_explode_missile, texture_sm_explosion };
-    
     run(&mut game, CLIENT_WIDTH, CLIENT_HEIGHT, Settings{
         background_color: Some(BLACK),
-        auto_scale: true,
+        scale_mode: ScaleMode::Fit,
         show_ups_fps: true,
+        record,
+        replay,
         ..Default::default()
-    }).await;
+    }).await
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mengine_quad::{audio::NullBackend, manifest::Manifest, replay::{self, Recording}};
+
+    //无窗口回放录制的游戏过程, 比较结束时的精灵状态, 可以在CI中运行
+    #[test]
+    fn test_replay() {
+        let manifest = Manifest::parse(&std::fs::read_to_string("static/assets.json").unwrap()).unwrap();
+        let mut audio = AudioManager::with_backend(NullBackend::default());
+        let resources = Resources::headless(manifest, &mut audio).unwrap();
+        let mut game = Game::new(resources, audio);
+        let recording = Recording::parse(&std::fs::read_to_string("tests/session.replay").unwrap()).unwrap();
+        replay::verify(&mut game, &recording).unwrap();
+    }
 }
@@ -1,4 +1,4 @@
-use mengine_quad::{run, engine::{Resource, GameEngine, Sprite, ScrollingBackground, BackgroundLayer, ScrollDir, BA_STOP}, Animation, State, Settings, Event, Drawable, rand_uuid};
+use mengine_quad::{text::{self, TextFont, TextStyle}, input::{InputMap, Binding, GamepadButton}, run, engine::{Resource, GameEngine, Sprite, ScrollingBackground, BackgroundLayer, ScrollDir, BA_STOP}, Animation, State, Flow, Settings, ScaleMode, Event, Drawable, rand_uuid};
 use macroquad::{prelude::*};
 use anyhow::Result;
 
@@ -6,10 +6,11 @@ pub const CLIENT_WIDTH:f32 = 256.0;
 pub const CLIENT_HEIGHT:f32 = 256.0;
 
 pub struct Game{
-    font: Font,
+    font: TextFont,
     background: ScrollingBackground,
     foreground: ScrollingBackground,
     sprites: Vec<Sprite>,
+    input: InputMap,
 }
 
 impl Game{
@@ -23,61 +24,54 @@ impl Game{
             }
         }
 
-        //向右移动风景图层
-        self.background.layers()[0].set_speed(16.0);
-        self.background.layers()[0].set_direction(direction);
-        self.background.update();
-        self.background.layers()[0].set_speed(0.0);
-        
-        //向右移动云彩图层
-        self.foreground.layers()[0].set_speed(4.0);
-        self.foreground.layers()[0].set_direction(direction);
-        self.foreground.update();
-        self.foreground.layers()[0].set_speed(0.0);
+        //0.1秒内平滑滚动, 风景图层每步移动16像素, 云彩图层按视差移动4像素
+        let delta = direction.viewport_direction() * 16.0;
+        self.background.scroll_by(delta, 0.1);
+        self.foreground.scroll_by(delta, 0.1);
     }
 }
 
 impl State for Game{
-    fn event(&mut self, _event: Event) {
-        
+    fn event(&mut self, event: Event) {
+        self.input.event(event);
     }
-    fn update(&mut self) {
+    fn update(&mut self) -> Result<Flow> {
         self.update_sprites();
         // self.background.update();
         // self.foreground.update();
         
-        if is_key_pressed(KeyCode::Left){
+        if self.input.is_pressed("left"){
             self.drive(ScrollDir::Left)
-        }else if is_key_pressed(KeyCode::Right){
+        }else if self.input.is_pressed("right"){
             self.drive(ScrollDir::Right)
-        }else if is_key_pressed(KeyCode::Up){
+        }else if self.input.is_pressed("up"){
             self.drive(ScrollDir::Up)
-        }else if is_key_pressed(KeyCode::Down){
+        }else if self.input.is_pressed("down"){
             self.drive(ScrollDir::Down)
         }
+        self.input.tick();
+        Ok(Flow::Continue)
     }
-    fn draw(&mut self){
+    fn draw(&mut self) -> Result<()> {
         self.background.draw();
         self.draw_sprites();
         self.foreground.draw();
 
         let hint = "上下左右键移动";
-        let font_size = 16;
-        let hint_x = 5.;
-        let hint_baseline = 20.;
-        let size = measure_text(hint, Some(self.font), font_size, 1.0);
-        draw_rectangle(hint_x, hint_baseline-font_size as f32 + 2., size.width, font_size as f32, Color::from_rgba(0, 0, 0, 100));
-        draw_text_ex(
-            hint,
-            hint_x,
-            hint_baseline,
-            TextParams {
-                font_size,
-                font: self.font,
-                color: WHITE,
-                ..Default::default()
-            },
-        );
+        let style = TextStyle{ font: self.font.clone(), size: 16, color: WHITE, ..Default::default() };
+        let (hint_x, hint_y) = (5., 6.);
+        let size = text::measure(hint, None, &style);
+        draw_rectangle(hint_x, hint_y, size.x, size.y, Color::from_rgba(0, 0, 0, 100));
+        text::draw_text(hint, hint_x, hint_y, &style);
+        Ok(())
+    }
+    fn wants_screenshot(&mut self) -> bool {
+        is_key_pressed(KeyCode::F12)
+    }
+    fn screenshot(&mut self, image: Image) {
+        //F12截图
+        #[cfg(not(target_arch = "wasm32"))]
+        image.export_png("screenshot.png");
     }
 }
 
@@ -90,6 +84,10 @@ impl GameEngine for Game{
         &self.sprites
     }
 
+    fn backgrounds_mut(&mut self) -> Vec<&mut ScrollingBackground> {
+        vec![&mut self.background, &mut self.foreground]
+    }
+
     fn sprite_dying(&mut self, _sprite_dying_id: usize) {
         
     }
@@ -99,15 +97,20 @@ impl GameEngine for Game{
     }
 }
 
-fn window_conf() -> Conf {
-    Conf {
-        window_title: "Aliens".to_owned(),
-        window_width: 250,
-        window_height: 150,
+fn settings() -> Settings {
+    Settings{
+        background_color: Some(WHITE),
+        scale_mode: ScaleMode::PixelPerfect,
+        show_ups_fps: true,
+        window_size: Some(vec2(250., 150.)),
         ..Default::default()
     }
 }
 
+fn window_conf() -> Conf {
+    settings().window_conf("Wanderer")
+}
+
 #[macroquad::main(window_conf)]
 async fn main() -> Result<()> {
     
@@ -116,7 +119,7 @@ async fn main() -> Result<()> {
     // 加载素材
     draw_text("Loading...", screen_width()/2., screen_height()/2., 20., BLACK);
 
-    let font = load_ttf_font("static/VonwaonBitmap-16pxLite.ttf").await?;
+    let font = text::load_font("static/VonwaonBitmap-16pxLite.ttf").await?;
 
     let texture_background_clouds = Drawable::Texture2D(Texture2D::from_image(&load_image("static/Background_Clouds.png").await?));
     let texture_background_landscape = Drawable::Texture2D(Texture2D::from_image(&load_image("static/Background_Landscape.png").await?));
@@ -135,12 +138,13 @@ async fn main() -> Result<()> {
 
     //创建滚动前景和云彩图层
     let mut foreground = ScrollingBackground::new();
-    let fg_clouds_layer = BackgroundLayer::new(
+    let mut fg_clouds_layer = BackgroundLayer::new(
         texture_background_clouds,
         Rect::new(64.0, 64.0, 320.0, 320.0),
         0.0,
         ScrollDir::Left,
     );
+    fg_clouds_layer.set_parallax(vec2(0.25, 0.25));
     foreground.add_layer(fg_clouds_layer);
     
 
@@ -163,14 +167,19 @@ async fn main() -> Result<()> {
     );
     person.set_position(115.0, 112.0);
     
-    let mut game = Game{ sprites: vec![person], foreground, background, font };
+    //方向键和手柄十字键
+    let mut input = InputMap::new();
+    for (action, key, button) in [
+        ("left", KeyCode::Left, GamepadButton::DPadLeft),
+        ("right", KeyCode::Right, GamepadButton::DPadRight),
+        ("up", KeyCode::Up, GamepadButton::DPadUp),
+        ("down", KeyCode::Down, GamepadButton::DPadDown),
+    ]{
+        input.bind(action, Binding::Key(key));
+        input.bind(action, Binding::Gamepad(button));
+    }
     
-    run(&mut game, CLIENT_WIDTH, CLIENT_HEIGHT, Settings{
-        background_color: Some(WHITE),
-        auto_scale: true,
-        show_ups_fps: true,
-        ..Default::default()
-    }).await;
-
-    Ok(())
+    let mut game = Game{ sprites: vec![person], foreground, background, font, input };
+    
+    run(&mut game, CLIENT_WIDTH, CLIENT_HEIGHT, settings()).await
 }
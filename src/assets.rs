@@ -0,0 +1,570 @@
+use std::{collections::HashMap, future::Future, marker::PhantomData, pin::Pin, task::{Context as TaskContext, Poll, Waker}};
+
+use anyhow::{anyhow, Context, Result};
+use macroquad::{audio::Sound, logging::warn, prelude::{next_frame, clear_background, draw_rectangle, draw_rectangle_lines, set_camera, set_default_camera, Camera2D, Color, Rect, Texture2D, BLACK, WHITE}};
+
+use crate::{embed, audio::{load_sound, AudioManager, SoundHandle, SoundOptions}, text::{self, TextFont, TextStyle, Align}, Animation, Drawable, Event, Flow, State};
+
+//资源管理: 按key排队加载图片、声音、字体和数据文件, 同时加载并报告进度, 同一路径只加载一次.
+//文件通过embed模块读取, 设置了内嵌资源时优先使用内嵌的文件
+
+/// 加载完成的资源
+#[derive(Clone)]
+pub enum Asset {
+    Texture(Texture2D),
+    Sound(Sound),
+    Font(TextFont),
+    Data(Vec<u8>),
+}
+
+/// 资源句柄, T为资源类型(Texture2D、Sound、TextFont、Vec<u8>)
+pub struct Handle<T> {
+    index: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Handle<T> {
+        Handle { index, _marker: PhantomData }
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+pub type TextureHandle = Handle<Texture2D>;
+pub type SoundAssetHandle = Handle<Sound>;
+pub type FontHandle = Handle<TextFont>;
+pub type DataHandle = Handle<Vec<u8>>;
+
+pub type AssetFuture = Pin<Box<dyn Future<Output = Result<Asset>>>>;
+
+enum AssetState {
+    /// 等待加载
+    Pending(AssetFuture),
+    Loaded(Asset),
+    Failed(String),
+}
+
+type AssetLoader = fn(String) -> AssetFuture;
+
+struct AssetEntry {
+    key: String,
+    path: String,
+    state: AssetState,
+    /// 重新加载文件, 用于热重载
+    loader: Option<AssetLoader>,
+}
+
+fn texture_loader(path: String) -> AssetFuture {
+    Box::pin(async move {
+        let texture = embed::load_texture(&path).await.with_context(|| format!("图片加载失败: {}", path))?;
+        Ok(Asset::Texture(texture))
+    })
+}
+
+fn sound_loader(path: String) -> AssetFuture {
+    Box::pin(async move { Ok(Asset::Sound(load_sound(&path).await?)) })
+}
+
+fn font_loader(path: String) -> AssetFuture {
+    Box::pin(async move { Ok(Asset::Font(text::load_font(&path).await?)) })
+}
+
+fn data_loader(path: String) -> AssetFuture {
+    Box::pin(async move {
+        Ok(Asset::Data(embed::load_file(&path).await?))
+    })
+}
+
+/// 热重载后需要替换的资源
+#[derive(Clone, Debug, Default)]
+pub struct Reload {
+    /// (旧图片, 新图片)
+    pub textures: Vec<(Texture2D, Texture2D)>,
+    /// (动画图片, 旧的帧, 新的帧)
+    pub animations: Vec<(Texture2D, Vec<Rect>, Vec<Rect>)>,
+}
+
+impl Reload {
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty() && self.animations.is_empty()
+    }
+
+    pub fn append(&mut self, other: Reload) {
+        self.textures.extend(other.textures);
+        self.animations.extend(other.animations);
+    }
+
+    fn new_texture(&self, texture: Texture2D) -> Option<Texture2D> {
+        self.textures.iter().find(|(old, _)| *old == texture).map(|(_, new)| *new)
+    }
+
+    /// 替换Drawable中的图片, 返回是否替换
+    pub fn apply_drawable(&self, drawable: &mut Drawable) -> bool {
+        let texture = match drawable {
+            Drawable::Texture2D(texture) => texture,
+            Drawable::NineSlice(nine) => &mut nine.texture,
+            _ => return false,
+        };
+        match self.new_texture(*texture) {
+            Some(new) => {
+                *texture = new;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 替换动画的图片和帧, 保留动画的当前状态
+    pub fn apply_animation(&self, animation: &mut Animation) -> bool {
+        let mut image = animation.image().clone();
+        let mut changed = self.apply_drawable(&mut image);
+        if let Drawable::Texture2D(texture) = &image {
+            if let Some((_, _, frames)) = self.animations.iter().find(|(t, old, _)| t == texture && old == animation.frames()) {
+                animation.set_frames(frames.clone());
+                changed = true;
+            }
+        }
+        animation.set_image(image);
+        changed
+    }
+}
+
+/// 检查文件修改时间的监视器, 只用于桌面平台
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct FileWatcher {
+    files: HashMap<String, Option<std::time::SystemTime>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileWatcher {
+    fn modified(path: &str) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn watch(&mut self, path: &str) {
+        self.files.insert(path.to_string(), Self::modified(path));
+    }
+
+    /// 上次检查后修改过的文件
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = vec![];
+        for (path, time) in self.files.iter_mut() {
+            let modified = Self::modified(path);
+            if modified.is_some() && modified != *time {
+                *time = modified;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+/// 加载进度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl Progress {
+    /// 完成比例 0.0~1.0
+    pub fn ratio(&self) -> f32 {
+        if self.total == 0 {
+            1.
+        } else {
+            (self.loaded + self.failed) as f32 / self.total as f32
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.loaded + self.failed == self.total
+    }
+}
+
+/// 资源管理器
+#[derive(Default)]
+pub struct AssetManager {
+    entries: Vec<AssetEntry>,
+    keys: HashMap<String, usize>,
+    paths: HashMap<String, usize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<FileWatcher>,
+    /// 正在重新加载的资源
+    reloading: Vec<(usize, AssetFuture)>,
+}
+
+impl AssetManager {
+    pub fn new() -> AssetManager {
+        Self::default()
+    }
+
+    /// 添加加载任务, 同一路径只加载一次; 同一key指向最后一次添加的资源
+    pub fn queue_with<T>(&mut self, key: &str, path: &str, future: AssetFuture) -> Handle<T> {
+        self.queue_entry(key, path, future, None)
+    }
+
+    fn queue_entry<T>(&mut self, key: &str, path: &str, future: AssetFuture, loader: Option<AssetLoader>) -> Handle<T> {
+        let index = match self.paths.get(path) {
+            Some(index) => *index,
+            None => {
+                #[cfg(not(target_arch = "wasm32"))]
+                if let (Some(watcher), Some(_)) = (self.watcher.as_mut(), loader) {
+                    watcher.watch(path);
+                }
+                self.entries.push(AssetEntry {
+                    key: key.to_string(),
+                    path: path.to_string(),
+                    state: AssetState::Pending(future),
+                    loader,
+                });
+                self.paths.insert(path.to_string(), self.entries.len() - 1);
+                self.entries.len() - 1
+            }
+        };
+        self.keys.insert(key.to_string(), index);
+        Handle::new(index)
+    }
+
+    fn queue_loader<T>(&mut self, key: &str, path: &str, loader: AssetLoader) -> Handle<T> {
+        self.queue_entry(key, path, loader(path.to_string()), Some(loader))
+    }
+
+    /// 加载图片, 使用最近邻过滤
+    pub fn queue_texture(&mut self, key: &str, path: &str) -> TextureHandle {
+        self.queue_loader(key, path, texture_loader)
+    }
+
+    /// 加载声音, 只支持WAV和OGG格式
+    pub fn queue_sound(&mut self, key: &str, path: &str) -> SoundAssetHandle {
+        self.queue_loader(key, path, sound_loader)
+    }
+
+    /// 加载TTF字体或BMFont(.fnt)位图字体
+    pub fn queue_font(&mut self, key: &str, path: &str) -> FontHandle {
+        self.queue_loader(key, path, font_loader)
+    }
+
+    /// 加载数据文件
+    pub fn queue_data(&mut self, key: &str, path: &str) -> DataHandle {
+        self.queue_loader(key, path, data_loader)
+    }
+
+    /// 开启热重载, 监视已添加和之后添加的文件, 只用于桌面平台
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch(&mut self) {
+        let mut watcher = FileWatcher::default();
+        for entry in self.entries.iter().filter(|e| e.loader.is_some()) {
+            watcher.watch(&entry.path);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    /// 重新加载修改过的文件, 每帧调用一次. 返回已加载完成的图片替换, 旧图片不会被删除
+    pub fn reload(&mut self) -> Reload {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = self.watcher.as_mut() {
+            for path in watcher.changed() {
+                if let Some(index) = self.paths.get(&path).copied() {
+                    if let (Some(loader), false) = (self.entries[index].loader, self.reloading.iter().any(|(i, _)| *i == index)) {
+                        self.reloading.push((index, loader(path)));
+                    }
+                }
+            }
+        }
+        let mut reload = Reload::default();
+        let mut cx = TaskContext::from_waker(Waker::noop());
+        let entries = &mut self.entries;
+        self.reloading.retain_mut(|(index, future)| {
+            let result = match future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return true,
+            };
+            let entry = &mut entries[*index];
+            match result {
+                Ok(asset) => {
+                    if let (AssetState::Loaded(Asset::Texture(old)), Asset::Texture(new)) = (&entry.state, &asset) {
+                        reload.textures.push((*old, *new));
+                    }
+                    entry.state = AssetState::Loaded(asset);
+                }
+                //重新加载失败时保留原来的资源
+                Err(err) => warn!("资源\"{}\"重新加载失败: {:#}", entry.key, err),
+            }
+            false
+        });
+        reload
+    }
+
+    /// 推进所有等待中的加载任务, 每帧调用一次
+    pub fn poll(&mut self) -> Progress {
+        let mut cx = TaskContext::from_waker(Waker::noop());
+        for entry in self.entries.iter_mut() {
+            if let AssetState::Pending(future) = &mut entry.state {
+                if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                    entry.state = match result {
+                        Ok(asset) => AssetState::Loaded(asset),
+                        Err(err) => AssetState::Failed(format!("{:#}", err)),
+                    };
+                }
+            }
+        }
+        self.progress()
+    }
+
+    pub fn progress(&self) -> Progress {
+        let mut progress = Progress { total: self.entries.len(), ..Default::default() };
+        for entry in &self.entries {
+            match entry.state {
+                AssetState::Loaded(_) => progress.loaded += 1,
+                AssetState::Failed(_) => progress.failed += 1,
+                AssetState::Pending(_) => (),
+            }
+        }
+        progress
+    }
+
+    /// 加载失败的资源(key, 错误信息)
+    pub fn errors(&self) -> Vec<(&str, &str)> {
+        self.entries.iter().filter_map(|e| match &e.state {
+            AssetState::Failed(err) => Some((e.key.as_str(), err.as_str())),
+            _ => None,
+        }).collect()
+    }
+
+    /// 第一个加载失败的资源转换为错误
+    pub fn check(&self) -> Result<()> {
+        match self.errors().first() {
+            Some((key, err)) => Err(anyhow!("资源\"{}\"加载失败: {}", key, err)),
+            None => Ok(()),
+        }
+    }
+
+    /// 等待所有资源加载完成, 有资源加载失败时返回错误
+    pub async fn load_all(&mut self) -> Result<()> {
+        while !self.poll().is_done() {
+            next_frame().await;
+        }
+        self.check()
+    }
+
+    pub fn handle<T>(&self, key: &str) -> Option<Handle<T>> {
+        self.keys.get(key).map(|index| Handle::new(*index))
+    }
+
+    pub fn path<T>(&self, handle: Handle<T>) -> Option<&str> {
+        self.entries.get(handle.index).map(|e| e.path.as_str())
+    }
+
+    pub fn asset<T>(&self, handle: Handle<T>) -> Option<&Asset> {
+        match self.entries.get(handle.index).map(|e| &e.state) {
+            Some(AssetState::Loaded(asset)) => Some(asset),
+            _ => None,
+        }
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<Texture2D> {
+        match self.asset(handle) {
+            Some(Asset::Texture(texture)) => Some(*texture),
+            _ => None,
+        }
+    }
+
+    pub fn sound(&self, handle: SoundAssetHandle) -> Option<Sound> {
+        match self.asset(handle) {
+            Some(Asset::Sound(sound)) => Some(*sound),
+            _ => None,
+        }
+    }
+
+    pub fn font(&self, handle: FontHandle) -> Option<TextFont> {
+        match self.asset(handle) {
+            Some(Asset::Font(font)) => Some(font.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self, handle: DataHandle) -> Option<&[u8]> {
+        match self.asset(handle) {
+            Some(Asset::Data(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn drawable(&self, handle: TextureHandle) -> Option<Drawable> {
+        self.texture(handle).map(Drawable::Texture2D)
+    }
+
+    pub fn animation(&self, handle: TextureHandle, frames: Vec<Rect>, fps: f64) -> Option<Animation> {
+        self.drawable(handle).map(|image| Animation::new(image, frames, fps))
+    }
+
+    /// 添加声音到音频管理器
+    pub fn add_sound_to(&self, audio: &mut AudioManager, handle: SoundAssetHandle, options: SoundOptions) -> Option<SoundHandle> {
+        self.sound(handle).map(|sound| audio.add_sound(sound, options))
+    }
+}
+
+/// 内置的加载画面, 显示进度条
+pub struct LoadingScreen {
+    pub assets: AssetManager,
+    pub text: String,
+    pub style: TextStyle,
+    pub background_color: Color,
+    pub bar_color: Color,
+    progress: Progress,
+    width: f32,
+    height: f32,
+}
+
+impl LoadingScreen {
+    /// width和height为游戏画面大小, 和run()的参数相同
+    pub fn new(width: f32, height: f32, assets: AssetManager) -> LoadingScreen {
+        LoadingScreen {
+            assets,
+            text: String::from("Loading..."),
+            style: TextStyle { align: Align::Center, ..Default::default() },
+            background_color: BLACK,
+            bar_color: WHITE,
+            progress: Progress::default(),
+            width,
+            height,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// 显示加载画面直到所有资源加载完成, 画面拉伸到整个窗口
+    pub async fn load(mut self) -> Result<AssetManager> {
+        loop {
+            self.update()?;
+            set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., self.width, self.height)));
+            self.draw()?;
+            set_default_camera();
+            if self.progress.is_done() {
+                break;
+            }
+            next_frame().await;
+        }
+        self.assets.check()?;
+        Ok(self.assets)
+    }
+}
+
+impl State for LoadingScreen {
+    fn event(&mut self, _event: Event) {}
+
+    fn update(&mut self) -> Result<Flow> {
+        self.progress = self.assets.poll();
+        Ok(if self.progress.is_done() { Flow::Quit } else { Flow::Continue })
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let (width, height) = (self.width, self.height);
+        clear_background(self.background_color);
+        let bar = Rect::new(width * 0.2, height / 2., width * 0.6, 12.);
+        let line = self.style.size as f32 * 2.;
+        text::draw_text_box(&self.text, Rect::new(bar.x, bar.y - line, bar.w, line), &self.style);
+        draw_rectangle_lines(bar.x, bar.y, bar.w, bar.h, 2., self.bar_color);
+        draw_rectangle(bar.x, bar.y, bar.w * self.progress.ratio(), bar.h, self.bar_color);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 第n次轮询时完成的任务
+    struct Delay(usize, Option<Asset>);
+
+    impl Future for Delay {
+        type Output = Result<Asset>;
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<Result<Asset>> {
+            if self.0 == 0 {
+                Poll::Ready(self.1.take().ok_or_else(|| anyhow!("not found")))
+            } else {
+                self.0 -= 1;
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_asset_manager() {
+        let mut assets = AssetManager::new();
+        let level: DataHandle = assets.queue_with("level", "level.txt", Box::pin(Delay(1, Some(Asset::Data(vec![1, 2])))));
+        let map: DataHandle = assets.queue_with("map", "map.txt", Box::pin(Delay(0, Some(Asset::Data(vec![3])))));
+        let missing: DataHandle = assets.queue_with("missing", "missing.txt", Box::pin(Delay(0, None)));
+        //同一路径只加载一次
+        let cached: DataHandle = assets.queue_with("level2", "level.txt", Box::pin(Delay(0, None)));
+        assert_eq!(cached, level);
+        assert_eq!(assets.progress(), Progress { loaded: 0, failed: 0, total: 3 });
+
+        assert_eq!(assets.poll(), Progress { loaded: 1, failed: 1, total: 3 });
+        assert_eq!(assets.data(level), None);
+        assert_eq!(assets.data(map), Some(&[3u8][..]));
+        assert_eq!(assets.poll(), Progress { loaded: 2, failed: 1, total: 3 });
+        assert!(assets.progress().is_done());
+        assert_eq!(assets.data(assets.handle("level2").unwrap()), Some(&[1u8, 2][..]));
+        assert_eq!(assets.data(missing), None);
+        assert_eq!(assets.errors(), vec![("missing", "not found")]);
+        assert!(assets.check().is_err());
+    }
+
+    #[test]
+    fn test_reload_failed() {
+        embed::set_embedded(embed::TEST_FILES);
+        let mut assets = AssetManager::new();
+        let old = Texture2D::empty();
+        let handle: TextureHandle = assets.queue_entry("broken", "broken.png", Box::pin(Delay(0, Some(Asset::Texture(old)))), Some(texture_loader));
+        assets.poll();
+        //模拟文件被改成不完整的PNG
+        assets.reloading.push((handle.index, texture_loader("broken.png".to_string())));
+        let reload = assets.reload();
+        assert!(reload.textures.is_empty());
+        assert!(assets.reloading.is_empty());
+        assert_eq!(assets.texture(handle), Some(old));
+    }
+
+    #[test]
+    fn test_reload_animation() {
+        let texture = Texture2D::empty();
+        let frames = vec![Rect::new(0., 0., 10., 10.), Rect::new(0., 10., 10., 10.)];
+        let mut animation = Animation::new(Drawable::Texture2D(texture), frames.clone(), 10.);
+        animation.set_current_frame(1);
+        let reload = Reload {
+            textures: vec![],
+            animations: vec![(texture, frames, vec![Rect::new(0., 0., 12., 12.)])],
+        };
+        assert!(reload.apply_animation(&mut animation));
+        assert_eq!(animation.frames(), &[Rect::new(0., 0., 12., 12.)]);
+        assert_eq!(animation.current_frame(), 0);
+        //帧已经替换过, 不再匹配
+        assert!(!reload.apply_animation(&mut animation));
+    }
+}
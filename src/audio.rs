@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use macroquad::{audio::{self, PlaySoundParams, Sound}, prelude::Vec2};
+
+use crate::{current_timestamp, embed, AudioType};
+
+//音频管理: 声音句柄、音量分组、背景音乐淡入淡出和同一声音的并发数限制
+
+/// 声音句柄
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SoundHandle(usize);
+
+/// 音量分组, 最终音量 = 主音量 * 分组音量 * 声音音量
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AudioGroup {
+    Music,
+    Sfx,
+}
+
+/// 音频后端, 实际播放声音
+pub trait AudioBackend {
+    type Sound;
+    fn play(&mut self, sound: &Self::Sound, looped: bool, volume: f32);
+    fn stop(&mut self, sound: &Self::Sound);
+    fn set_volume(&mut self, sound: &Self::Sound, volume: f32);
+    /// 声像 -1.0(左)~1.0(右), 不支持声像的后端忽略
+    fn set_pan(&mut self, _sound: &Self::Sound, _pan: f32) {}
+}
+
+/// macroquad音频后端. macroquad 0.3不支持声像, set_pan被忽略;
+/// 音量和停止作用于同一个Sound的所有播放
+#[derive(Default)]
+pub struct QuadBackend;
+
+impl AudioBackend for QuadBackend {
+    type Sound = Sound;
+
+    fn play(&mut self, sound: &Sound, looped: bool, volume: f32) {
+        audio::play_sound(*sound, PlaySoundParams { looped, volume });
+    }
+
+    fn stop(&mut self, sound: &Sound) {
+        audio::stop_sound(*sound);
+    }
+
+    fn set_volume(&mut self, sound: &Sound, volume: f32) {
+        audio::set_sound_volume(*sound, volume);
+    }
+}
+
+/// 后端收到的命令, 由NullBackend记录
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioCommand {
+    Play { sound: String, looped: bool, volume: f32 },
+    Stop { sound: String },
+    SetVolume { sound: String, volume: f32 },
+    SetPan { sound: String, pan: f32 },
+}
+
+/// 不播放声音的后端, 记录收到的命令, 用于无音频设备时测试
+#[derive(Default)]
+pub struct NullBackend {
+    pub commands: Vec<AudioCommand>,
+}
+
+impl AudioBackend for NullBackend {
+    type Sound = String;
+
+    fn play(&mut self, sound: &String, looped: bool, volume: f32) {
+        self.commands.push(AudioCommand::Play { sound: sound.clone(), looped, volume });
+    }
+
+    fn stop(&mut self, sound: &String) {
+        self.commands.push(AudioCommand::Stop { sound: sound.clone() });
+    }
+
+    fn set_volume(&mut self, sound: &String, volume: f32) {
+        self.commands.push(AudioCommand::SetVolume { sound: sound.clone(), volume });
+    }
+
+    fn set_pan(&mut self, sound: &String, pan: f32) {
+        self.commands.push(AudioCommand::SetPan { sound: sound.clone(), pan });
+    }
+}
+
+/// 声音选项
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundOptions {
+    pub group: AudioGroup,
+    /// 声音自身的音量
+    pub volume: f32,
+    /// 同时播放的最大数量, 0为不限制
+    pub max_voices: usize,
+    /// 声音时长(秒), 用于计算正在播放的数量
+    pub length: f32,
+}
+
+impl Default for SoundOptions {
+    fn default() -> Self {
+        SoundOptions {
+            group: AudioGroup::Sfx,
+            volume: 1.,
+            max_voices: 0,
+            length: 1.,
+        }
+    }
+}
+
+impl SoundOptions {
+    pub fn music() -> SoundOptions {
+        SoundOptions { group: AudioGroup::Music, ..Default::default() }
+    }
+
+    pub fn sfx(max_voices: usize, length: f32) -> SoundOptions {
+        SoundOptions { max_voices, length, ..Default::default() }
+    }
+}
+
+struct SoundEntry<S> {
+    sound: S,
+    options: SoundOptions,
+    /// 正在播放的声音的结束时间
+    voices: Vec<f64>,
+}
+
+/// 正在播放或淡出的背景音乐
+struct MusicTrack {
+    handle: SoundHandle,
+    /// 淡入淡出系数 0.0~1.0
+    fade: f32,
+    /// 每秒的系数变化, 正数淡入, 负数淡出
+    fade_speed: f32,
+}
+
+/// 音频管理器
+pub struct AudioManager<B: AudioBackend = QuadBackend> {
+    backend: B,
+    sounds: Vec<SoundEntry<B::Sound>>,
+    paths: HashMap<String, SoundHandle>,
+    master_volume: f32,
+    group_volumes: HashMap<AudioGroup, f32>,
+    music: Option<MusicTrack>,
+    fading_out: Vec<MusicTrack>,
+    /// 正在循环播放的声音发射器(增益, 声像)
+    spatial_loops: HashMap<SoundHandle, (f32, f32)>,
+    /// 管理器时钟(秒)
+    time: f64,
+    last_tick: Option<f64>,
+}
+
+/// 加载声音, 只支持WAV和OGG格式
+pub async fn load_sound(path: &str) -> Result<Sound> {
+    let audio_type = AudioType::test(path);
+    if !audio_type.is_supported() {
+        return Err(anyhow!("不支持的音频格式{:?}: {}", audio_type, path));
+    }
+    let data = embed::load_file(path).await?;
+    audio::load_sound_from_bytes(&data).await.with_context(|| format!("声音加载失败: {}", path))
+}
+
+impl AudioManager<QuadBackend> {
+    pub fn new() -> AudioManager<QuadBackend> {
+        Self::with_backend(QuadBackend)
+    }
+
+    /// 加载声音并添加到管理器, 同一路径只加载一次
+    pub async fn load(&mut self, path: &str, options: SoundOptions) -> Result<SoundHandle> {
+        if let Some(handle) = self.paths.get(path) {
+            return Ok(*handle);
+        }
+        let sound = load_sound(path).await?;
+        let handle = self.add_sound(sound, options);
+        self.paths.insert(path.to_string(), handle);
+        Ok(handle)
+    }
+}
+
+impl Default for AudioManager<QuadBackend> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: AudioBackend> AudioManager<B> {
+    pub fn with_backend(backend: B) -> AudioManager<B> {
+        AudioManager {
+            backend,
+            sounds: vec![],
+            paths: HashMap::new(),
+            master_volume: 1.,
+            group_volumes: HashMap::new(),
+            music: None,
+            fading_out: vec![],
+            spatial_loops: HashMap::new(),
+            time: 0.,
+            last_tick: None,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn add_sound(&mut self, sound: B::Sound, options: SoundOptions) -> SoundHandle {
+        self.sounds.push(SoundEntry { sound, options, voices: vec![] });
+        SoundHandle(self.sounds.len() - 1)
+    }
+
+    pub fn sound(&self, handle: SoundHandle) -> Option<&B::Sound> {
+        self.sounds.get(handle.0).map(|e| &e.sound)
+    }
+
+    pub fn set_options(&mut self, handle: SoundHandle, options: SoundOptions) {
+        if let Some(entry) = self.sounds.get_mut(handle.0) {
+            entry.options = options;
+        }
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume.clamp(0., 1.);
+        self.apply_music_volume();
+    }
+
+    pub fn group_volume(&self, group: AudioGroup) -> f32 {
+        self.group_volumes.get(&group).copied().unwrap_or(1.)
+    }
+
+    pub fn set_group_volume(&mut self, group: AudioGroup, volume: f32) {
+        self.group_volumes.insert(group, volume.clamp(0., 1.));
+        self.apply_music_volume();
+    }
+
+    /// 声音的最终音量
+    pub fn volume_of(&self, handle: SoundHandle) -> f32 {
+        match self.sounds.get(handle.0) {
+            Some(entry) => self.master_volume * self.group_volume(entry.options.group) * entry.options.volume,
+            None => 0.,
+        }
+    }
+
+    /// 正在播放的数量
+    pub fn voices(&self, handle: SoundHandle) -> usize {
+        self.sounds.get(handle.0).map(|e| e.voices.len()).unwrap_or(0)
+    }
+
+    /// 播放一次声音, 达到最大同时播放数量时不播放并返回false
+    pub fn play(&mut self, handle: SoundHandle) -> bool {
+        self.play_spatial(handle, 1., 0.)
+    }
+
+    /// 按增益和声像播放一次声音
+    pub fn play_spatial(&mut self, handle: SoundHandle, gain: f32, pan: f32) -> bool {
+        let volume = self.volume_of(handle) * gain;
+        let time = self.time;
+        let entry = match self.sounds.get_mut(handle.0) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        entry.voices.retain(|end| *end > time);
+        if entry.options.max_voices > 0 && entry.voices.len() >= entry.options.max_voices {
+            return false;
+        }
+        entry.voices.push(time + entry.options.length as f64);
+        self.backend.play(&entry.sound, false, volume);
+        if pan != 0. {
+            self.backend.set_pan(&entry.sound, pan);
+        }
+        true
+    }
+
+    /// 更新循环播放的声音发射器: 开始新的, 更新已有的增益和声像, 停止不再存在的.
+    /// 后端的音量和停止作用于整个声音, 会影响同一个声音正在播放的play/play_spatial,
+    /// 循环发射器应使用单独添加的声音
+    pub fn update_spatial_loops(&mut self, loops: HashMap<SoundHandle, (f32, f32)>) {
+        let stopped: Vec<SoundHandle> = self.spatial_loops.keys().filter(|h| !loops.contains_key(h)).copied().collect();
+        for handle in stopped {
+            self.spatial_loops.remove(&handle);
+            self.stop(handle);
+        }
+        for (handle, (gain, pan)) in loops {
+            let entry = match self.sounds.get(handle.0) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let volume = self.master_volume * self.group_volume(entry.options.group) * entry.options.volume * gain;
+            match self.spatial_loops.insert(handle, (gain, pan)) {
+                None => self.backend.play(&entry.sound, true, volume),
+                Some(old) if old.0 != gain => self.backend.set_volume(&entry.sound, volume),
+                _ => (),
+            }
+            self.backend.set_pan(&entry.sound, pan);
+        }
+    }
+
+    pub fn stop(&mut self, handle: SoundHandle) {
+        if let Some(entry) = self.sounds.get_mut(handle.0) {
+            entry.voices.clear();
+            self.backend.stop(&entry.sound);
+        }
+    }
+
+    /// 循环播放背景音乐, crossfade秒内从当前音乐淡入淡出到新音乐, 0为立即切换
+    pub fn play_music(&mut self, handle: SoundHandle, crossfade: f32) {
+        if self.sounds.get(handle.0).is_none() || self.music.as_ref().map(|m| m.handle) == Some(handle) {
+            return;
+        }
+        let speed = if crossfade > 0. { 1. / crossfade } else { f32::INFINITY };
+        if let Some(mut old) = self.music.take() {
+            old.fade_speed = -speed;
+            self.fading_out.push(old);
+        }
+        //正在淡出的同一首音乐重新淡入
+        let track = match self.fading_out.iter().position(|m| m.handle == handle) {
+            Some(i) => {
+                let mut track = self.fading_out.remove(i);
+                track.fade_speed = speed;
+                track
+            }
+            None => {
+                let fade = if crossfade > 0. { 0. } else { 1. };
+                let volume = self.volume_of(handle) * fade;
+                self.backend.play(&self.sounds[handle.0].sound, true, volume);
+                MusicTrack { handle, fade, fade_speed: speed }
+            }
+        };
+        self.music = Some(track);
+        //立即切换时马上停止旧音乐
+        self.update(0.);
+    }
+
+    /// fade_out秒内淡出并停止背景音乐
+    pub fn stop_music(&mut self, fade_out: f32) {
+        if let Some(mut track) = self.music.take() {
+            track.fade_speed = if fade_out > 0. { -1. / fade_out } else { f32::NEG_INFINITY };
+            self.fading_out.push(track);
+            self.update(0.);
+        }
+    }
+
+    pub fn current_music(&self) -> Option<SoundHandle> {
+        self.music.as_ref().map(|m| m.handle)
+    }
+
+    /// 按真实时间更新, 每帧调用
+    pub fn tick(&mut self) {
+        let now = current_timestamp();
+        let elapsed = self.last_tick.map(|last| ((now - last) / 1000.) as f32).unwrap_or(0.);
+        self.last_tick = Some(now);
+        self.update(elapsed);
+    }
+
+    /// 经过elapsed秒, 更新淡入淡出和正在播放的声音数量
+    pub fn update(&mut self, elapsed: f32) {
+        self.time += elapsed as f64;
+        let time = self.time;
+        for entry in &mut self.sounds {
+            entry.voices.retain(|end| *end > time);
+        }
+        let step = |track: &mut MusicTrack| {
+            let delta = track.fade_speed * elapsed;
+            if track.fade_speed.is_infinite() {
+                track.fade = if track.fade_speed > 0. { 1. } else { 0. };
+            } else if delta != 0. {
+                track.fade = (track.fade + delta).clamp(0., 1.);
+            }
+        };
+        if let Some(track) = self.music.as_mut() {
+            step(track);
+        }
+        for track in &mut self.fading_out {
+            step(track);
+        }
+        //停止已经淡出的音乐
+        let (stopped, fading): (Vec<MusicTrack>, Vec<MusicTrack>) = self.fading_out.drain(..).partition(|m| m.fade <= 0.);
+        self.fading_out = fading;
+        for track in stopped {
+            self.backend.stop(&self.sounds[track.handle.0].sound);
+        }
+        self.apply_music_volume();
+    }
+
+    fn apply_music_volume(&mut self) {
+        let tracks: Vec<(SoundHandle, f32)> = self.music.iter().chain(self.fading_out.iter()).map(|m| (m.handle, m.fade)).collect();
+        for (handle, fade) in tracks {
+            let volume = self.volume_of(handle) * fade;
+            self.backend.set_volume(&self.sounds[handle.0].sound, volume);
+        }
+    }
+}
+
+/// 距离衰减曲线
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Falloff {
+    /// 在最大距离内不衰减
+    None,
+    Linear,
+    /// 按 最小距离/距离 衰减, 超过最大距离静音
+    Inverse,
+    /// 按 (1 - t)^指数 衰减, t为最小和最大距离之间的比例
+    Exponential(f32),
+}
+
+/// 按距离计算增益 0.0~1.0, 小于最小距离为1.0, 超过最大距离为0.0
+pub fn attenuation(distance: f32, min_distance: f32, max_distance: f32, falloff: Falloff) -> f32 {
+    if distance <= min_distance {
+        return 1.;
+    }
+    if distance >= max_distance {
+        return 0.;
+    }
+    let t = (distance - min_distance) / (max_distance - min_distance);
+    match falloff {
+        Falloff::None => 1.,
+        Falloff::Linear => 1. - t,
+        Falloff::Inverse if min_distance > 0. => min_distance / distance,
+        Falloff::Inverse => 1. - t,
+        Falloff::Exponential(exponent) => (1. - t).powf(exponent),
+    }
+}
+
+/// 按水平偏移计算声像 -1.0(左)~1.0(右), 偏移达到width时完全偏向一侧.
+/// QuadBackend不支持声像, 只有增益起作用
+pub fn stereo_pan(dx: f32, width: f32) -> f32 {
+    if width <= 0. {
+        0.
+    } else {
+        (dx / width).clamp(-1., 1.)
+    }
+}
+
+/// 精灵的声音发射器, 增益和声像按精灵相对于听者的位置计算.
+/// QuadBackend忽略声像; looped为true时声音的音量由发射器控制, 不要再用play播放同一个声音
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioEmitter {
+    pub sound: SoundHandle,
+    /// 循环播放, 否则调用play后播放一次
+    pub looped: bool,
+    pub falloff: Falloff,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// 声像完全偏向一侧的水平距离, 后端不支持声像时无效
+    pub pan_width: f32,
+    pending: bool,
+}
+
+impl AudioEmitter {
+    pub fn new(sound: SoundHandle, looped: bool) -> AudioEmitter {
+        AudioEmitter {
+            sound,
+            looped,
+            falloff: Falloff::Linear,
+            min_distance: 50.,
+            max_distance: 600.,
+            pan_width: 300.,
+            pending: false,
+        }
+    }
+
+    /// 请求在下次更新时播放一次
+    pub fn play(&mut self) {
+        self.pending = true;
+    }
+
+    /// 取出播放请求
+    pub fn take_pending(&mut self) -> bool {
+        std::mem::replace(&mut self.pending, false)
+    }
+
+    /// 计算(增益, 声像)
+    pub fn spatial(&self, listener: Vec2, position: Vec2) -> (f32, f32) {
+        let gain = attenuation(position.distance(listener), self.min_distance, self.max_distance, self.falloff);
+        (gain, stereo_pan(position.x - listener.x, self.pan_width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager() -> (AudioManager<NullBackend>, SoundHandle, SoundHandle, SoundHandle) {
+        let mut audio = AudioManager::with_backend(NullBackend::default());
+        let missile = audio.add_sound("missile".to_string(), SoundOptions::sfx(2, 0.5));
+        let title = audio.add_sound("title".to_string(), SoundOptions::music());
+        let battle = audio.add_sound("battle".to_string(), SoundOptions::music());
+        (audio, missile, title, battle)
+    }
+
+    #[test]
+    fn test_voice_limit() {
+        let (mut audio, missile, _, _) = manager();
+        assert!(audio.play(missile));
+        assert!(audio.play(missile));
+        //最多同时播放2个
+        assert!(!audio.play(missile));
+        assert_eq!(audio.voices(missile), 2);
+        //播放结束后可以再次播放
+        audio.update(0.5);
+        assert_eq!(audio.voices(missile), 0);
+        assert!(audio.play(missile));
+        assert_eq!(audio.backend().commands.len(), 3);
+    }
+
+    #[test]
+    fn test_volume_groups() {
+        let (mut audio, missile, _, _) = manager();
+        audio.set_master_volume(0.5);
+        audio.set_group_volume(AudioGroup::Sfx, 0.5);
+        audio.set_group_volume(AudioGroup::Music, 2.);
+        assert_eq!(audio.volume_of(missile), 0.25);
+        assert_eq!(audio.group_volume(AudioGroup::Music), 1.);
+        audio.play(missile);
+        assert_eq!(audio.backend().commands.last(), Some(&AudioCommand::Play { sound: "missile".into(), looped: false, volume: 0.25 }));
+    }
+
+    #[test]
+    fn test_music_crossfade() {
+        let (mut audio, _, title, battle) = manager();
+        audio.play_music(title, 0.);
+        assert_eq!(audio.backend().commands[0], AudioCommand::Play { sound: "title".into(), looped: true, volume: 1. });
+        audio.backend_mut().commands.clear();
+
+        //1秒内从title淡入淡出到battle
+        audio.play_music(battle, 1.);
+        audio.update(0.25);
+        let commands = &audio.backend().commands;
+        assert_eq!(commands[0], AudioCommand::Play { sound: "battle".into(), looped: true, volume: 0. });
+        assert!(commands.ends_with(&[
+            AudioCommand::SetVolume { sound: "battle".into(), volume: 0.25 },
+            AudioCommand::SetVolume { sound: "title".into(), volume: 0.75 },
+        ]));
+        audio.update(1.);
+        assert!(audio.backend().commands.contains(&AudioCommand::Stop { sound: "title".into() }));
+        assert_eq!(audio.current_music(), Some(battle));
+
+        audio.backend_mut().commands.clear();
+        audio.stop_music(0.);
+        assert_eq!(audio.backend().commands, vec![AudioCommand::Stop { sound: "battle".into() }]);
+        assert_eq!(audio.current_music(), None);
+    }
+
+    #[test]
+    fn test_attenuation() {
+        assert_eq!(attenuation(10., 50., 150., Falloff::Linear), 1.);
+        assert_eq!(attenuation(100., 50., 150., Falloff::Linear), 0.5);
+        assert_eq!(attenuation(150., 50., 150., Falloff::Linear), 0.);
+        assert_eq!(attenuation(100., 50., 150., Falloff::None), 1.);
+        assert_eq!(attenuation(200., 50., 150., Falloff::None), 0.);
+        assert_eq!(attenuation(100., 50., 150., Falloff::Inverse), 0.5);
+        assert_eq!(attenuation(125., 50., 150., Falloff::Exponential(2.)), 0.0625);
+        assert_eq!(stereo_pan(-150., 300.), -0.5);
+        assert_eq!(stereo_pan(900., 300.), 1.);
+        assert_eq!(stereo_pan(10., 0.), 0.);
+
+        let emitter = AudioEmitter::new(SoundHandle(0), false);
+        let (gain, pan) = emitter.spatial(Vec2::new(0., 0.), Vec2::new(-325., 0.));
+        assert_eq!((gain, pan), (0.5, -1.));
+    }
+
+    #[test]
+    fn test_spatial_loops() {
+        let (mut audio, missile, _, _) = manager();
+        audio.play_spatial(missile, 0.5, -0.5);
+        assert_eq!(audio.backend().commands, vec![
+            AudioCommand::Play { sound: "missile".into(), looped: false, volume: 0.5 },
+            AudioCommand::SetPan { sound: "missile".into(), pan: -0.5 },
+        ]);
+        audio.backend_mut().commands.clear();
+        audio.update_spatial_loops(HashMap::from([(missile, (0.5, 0.))]));
+        audio.update_spatial_loops(HashMap::from([(missile, (0.25, 1.))]));
+        audio.update_spatial_loops(HashMap::new());
+        assert_eq!(audio.backend().commands, vec![
+            AudioCommand::Play { sound: "missile".into(), looped: true, volume: 0.5 },
+            AudioCommand::SetPan { sound: "missile".into(), pan: 0. },
+            AudioCommand::SetVolume { sound: "missile".into(), volume: 0.25 },
+            AudioCommand::SetPan { sound: "missile".into(), pan: 1. },
+            AudioCommand::Stop { sound: "missile".into() },
+        ]);
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use macroquad::{logging::warn, material::{gl_use_default_material, gl_use_material, load_material, Material, MaterialParams}, miniquad::UniformType, prelude::{clear_background, draw_texture_ex, render_target, set_camera, vec2, Camera2D, Color, DrawTextureParams, FilterMode, RenderTarget, Texture2D, BLACK, BLANK, WHITE}};
+
+use crate::current_timestamp;
+
+//后期处理: 游戏画面绘制完成后按顺序应用全屏效果
+
+/// 全屏效果, 参数可以在运行时修改
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// CRT扫描线, intensity为暗线的强度 0.0~1.0
+    Scanlines { intensity: f32, line_height: f32 },
+    /// 颜色调整, 1.0为不变
+    ColorGrade { brightness: f32, contrast: f32, saturation: f32, tint: Color },
+    /// 暗角, radius和softness相对于画面半对角线
+    Vignette { radius: f32, softness: f32, intensity: f32 },
+    /// 闪屏, 从color淡出, 结束后自动移除
+    Flash { color: Color, duration: f32, elapsed: f32 },
+    /// 淡入淡出, 颜色覆盖程度从from变为to
+    Fade { color: Color, from: f32, to: f32, duration: f32, elapsed: f32 },
+    /// 像素化, size为像素块大小
+    Pixelate { size: f32 },
+}
+
+/// 着色器参数值
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Uniform {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec4([f32; 4]),
+}
+
+impl Uniform {
+    fn uniform_type(&self) -> UniformType {
+        match self {
+            Uniform::Float(_) => UniformType::Float1,
+            Uniform::Vec2(_) => UniformType::Float2,
+            Uniform::Vec4(_) => UniformType::Float4,
+        }
+    }
+
+    fn set(&self, material: &Material, name: &str) {
+        match *self {
+            Uniform::Float(v) => material.set_uniform(name, v),
+            Uniform::Vec2(v) => material.set_uniform(name, v),
+            Uniform::Vec4(v) => material.set_uniform(name, v),
+        }
+    }
+}
+
+fn color4(color: Color) -> Uniform {
+    Uniform::Vec4([color.r, color.g, color.b, color.a])
+}
+
+impl Effect {
+    pub fn scanlines() -> Effect {
+        Effect::Scanlines { intensity: 0.25, line_height: 1. }
+    }
+
+    pub fn color_grade() -> Effect {
+        Effect::ColorGrade { brightness: 1., contrast: 1., saturation: 1., tint: WHITE }
+    }
+
+    pub fn vignette() -> Effect {
+        Effect::Vignette { radius: 0.8, softness: 0.5, intensity: 0.6 }
+    }
+
+    pub fn flash(color: Color, duration: f32) -> Effect {
+        Effect::Flash { color, duration, elapsed: 0. }
+    }
+
+    /// 从画面淡出到颜色
+    pub fn fade_out(color: Color, duration: f32) -> Effect {
+        Effect::Fade { color, from: 0., to: 1., duration, elapsed: 0. }
+    }
+
+    /// 从颜色淡入到画面
+    pub fn fade_in(color: Color, duration: f32) -> Effect {
+        Effect::Fade { color, from: 1., to: 0., duration, elapsed: 0. }
+    }
+
+    /// 黑屏淡出
+    pub fn fade_to_black(duration: f32) -> Effect {
+        Self::fade_out(BLACK, duration)
+    }
+
+    pub fn pixelate(size: f32) -> Effect {
+        Effect::Pixelate { size }
+    }
+
+    /// 推进闪屏和淡入淡出(秒)
+    pub fn update(&mut self, dt: f32) {
+        if let Effect::Flash { elapsed, duration, .. } | Effect::Fade { elapsed, duration, .. } = self {
+            *elapsed = (*elapsed + dt).min(*duration);
+        }
+    }
+
+    /// 闪屏和淡入淡出的颜色覆盖程度 0.0~1.0
+    pub fn amount(&self) -> f32 {
+        let progress = |elapsed: f32, duration: f32| if duration > 0. { (elapsed / duration).clamp(0., 1.) } else { 1. };
+        match *self {
+            Effect::Flash { elapsed, duration, .. } => 1. - progress(elapsed, duration),
+            Effect::Fade { from, to, elapsed, duration, .. } => from + (to - from) * progress(elapsed, duration),
+            _ => 1.,
+        }
+    }
+
+    /// 闪屏结束后移除, 淡入淡出结束后保持最后的状态
+    pub fn is_finished(&self) -> bool {
+        matches!(*self, Effect::Flash { elapsed, duration, .. } if elapsed >= duration)
+    }
+
+    /// 淡入淡出是否完成
+    pub fn is_done(&self) -> bool {
+        match *self {
+            Effect::Flash { elapsed, duration, .. } | Effect::Fade { elapsed, duration, .. } => elapsed >= duration,
+            _ => true,
+        }
+    }
+
+    /// 着色器名称, 相同名称的效果共用一个着色器
+    fn shader(&self) -> (&'static str, &'static str) {
+        match self {
+            Effect::Scanlines { .. } => ("scanlines", SCANLINES),
+            Effect::ColorGrade { .. } => ("color_grade", COLOR_GRADE),
+            Effect::Vignette { .. } => ("vignette", VIGNETTE),
+            Effect::Flash { .. } | Effect::Fade { .. } => ("overlay", OVERLAY),
+            Effect::Pixelate { .. } => ("pixelate", PIXELATE),
+        }
+    }
+
+    pub fn uniforms(&self) -> Vec<(&'static str, Uniform)> {
+        match *self {
+            Effect::Scanlines { intensity, line_height } => vec![("Intensity", Uniform::Float(intensity)), ("LineHeight", Uniform::Float(line_height.max(1.)))],
+            Effect::ColorGrade { brightness, contrast, saturation, tint } => vec![
+                ("Brightness", Uniform::Float(brightness)),
+                ("Contrast", Uniform::Float(contrast)),
+                ("Saturation", Uniform::Float(saturation)),
+                ("Tint", color4(tint)),
+            ],
+            Effect::Vignette { radius, softness, intensity } => vec![
+                ("Radius", Uniform::Float(radius)),
+                ("Softness", Uniform::Float(softness)),
+                ("Intensity", Uniform::Float(intensity)),
+            ],
+            Effect::Flash { color, .. } | Effect::Fade { color, .. } => vec![("Overlay", color4(color)), ("Amount", Uniform::Float(self.amount()))],
+            Effect::Pixelate { size } => vec![("PixelSize", Uniform::Float(size.max(1.)))],
+        }
+    }
+}
+
+/// 复用大小相同的一对渲染目标, 大小改变时删除旧的再创建. macroquad不会自动释放渲染目标
+pub fn reuse_targets(targets: &mut Option<(RenderTarget, RenderTarget)>, width: f32, height: f32) -> (RenderTarget, RenderTarget) {
+    let (width, height) = (width as u32, height as u32);
+    match *targets {
+        Some((a, b)) if a.texture.width() as u32 == width && a.texture.height() as u32 == height => (a, b),
+        old => {
+            if let Some((a, b)) = old {
+                a.delete();
+                b.delete();
+            }
+            let a = render_target(width, height);
+            let b = render_target(width, height);
+            a.texture.set_filter(FilterMode::Nearest);
+            b.texture.set_filter(FilterMode::Nearest);
+            *targets = Some((a, b));
+            (a, b)
+        }
+    }
+}
+
+/// 效果ID
+pub type EffectId = usize;
+
+/// 效果链, 由游戏状态持有, 通过State::effects交给run()
+#[derive(Default)]
+pub struct EffectChain {
+    effects: Vec<(EffectId, Effect)>,
+    next_id: EffectId,
+    materials: HashMap<&'static str, Option<Material>>,
+    targets: Option<(RenderTarget, RenderTarget)>,
+    last_tick: Option<f64>,
+}
+
+impl EffectChain {
+    pub fn new() -> EffectChain {
+        Self::default()
+    }
+
+    /// 添加效果到最后
+    pub fn push(&mut self, effect: Effect) -> EffectId {
+        self.next_id += 1;
+        self.effects.push((self.next_id, effect));
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: EffectId) -> Option<Effect> {
+        let index = self.effects.iter().position(|(i, _)| *i == id)?;
+        Some(self.effects.remove(index).1)
+    }
+
+    pub fn get(&self, id: EffectId) -> Option<&Effect> {
+        self.effects.iter().find(|(i, _)| *i == id).map(|(_, e)| e)
+    }
+
+    /// 修改效果参数
+    pub fn get_mut(&mut self, id: EffectId) -> Option<&mut Effect> {
+        self.effects.iter_mut().find(|(i, _)| *i == id).map(|(_, e)| e)
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// 推进效果(秒), 移除结束的闪屏
+    pub fn update(&mut self, dt: f32) {
+        for (_, effect) in self.effects.iter_mut() {
+            effect.update(dt);
+        }
+        self.effects.retain(|(_, e)| !e.is_finished());
+    }
+
+    /// 按实际经过的时间推进效果, run()每帧调用
+    pub fn tick(&mut self) {
+        let now = current_timestamp() / 1000.;
+        let dt = self.last_tick.map(|t| now - t).unwrap_or(0.);
+        self.last_tick = Some(now);
+        self.update(dt as f32);
+    }
+
+    fn material(&mut self, effect: &Effect) -> Option<Material> {
+        let (name, fragment) = effect.shader();
+        *self.materials.entry(name).or_insert_with(|| {
+            let uniforms = effect.uniforms().iter()
+                .map(|(n, u)| (n.to_string(), u.uniform_type()))
+                .chain(std::iter::once(("Resolution".to_string(), UniformType::Float2)))
+                .collect();
+            match load_material(VERTEX, fragment, MaterialParams { uniforms, ..Default::default() }) {
+                Ok(material) => Some(material),
+                Err(err) => {
+                    warn!("效果{}着色器编译失败: {:?}", name, err);
+                    None
+                }
+            }
+        })
+    }
+
+    /// 按顺序应用效果, 返回处理后的纹理
+    pub fn apply(&mut self, source: Texture2D, width: f32, height: f32) -> Texture2D {
+        if self.effects.is_empty() {
+            return source;
+        }
+        let (mut front, mut back) = reuse_targets(&mut self.targets, width, height);
+        let mut texture = source;
+        let effects: Vec<Effect> = self.effects.iter().map(|(_, e)| e.clone()).collect();
+        for effect in effects {
+            let material = match self.material(&effect) {
+                Some(material) => material,
+                None => continue,
+            };
+            set_camera(&Camera2D {
+                zoom: vec2(2. / width, 2. / height),
+                target: vec2(width / 2., height / 2.),
+                render_target: Some(front),
+                ..Default::default()
+            });
+            clear_background(BLANK);
+            gl_use_material(material);
+            for (name, uniform) in effect.uniforms() {
+                uniform.set(&material, name);
+            }
+            Uniform::Vec2([width, height]).set(&material, "Resolution");
+            draw_texture_ex(texture, 0., 0., WHITE, DrawTextureParams {
+                dest_size: Some(vec2(width, height)),
+                ..Default::default()
+            });
+            gl_use_default_material();
+            texture = front.texture;
+            std::mem::swap(&mut front, &mut back);
+        }
+        texture
+    }
+}
+
+const VERTEX: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+varying lowp vec2 uv;
+varying lowp vec4 color;
+uniform mat4 Model;
+uniform mat4 Projection;
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+}
+"#;
+
+const SCANLINES: &str = r#"#version 100
+precision mediump float;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+uniform vec2 Resolution;
+uniform float Intensity;
+uniform float LineHeight;
+void main() {
+    vec4 col = texture2D(Texture, uv);
+    float line = mod(floor(uv.y * Resolution.y / LineHeight), 2.0);
+    gl_FragColor = vec4(col.rgb * (1.0 - Intensity * line), col.a);
+}
+"#;
+
+const COLOR_GRADE: &str = r#"#version 100
+precision mediump float;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+uniform float Brightness;
+uniform float Contrast;
+uniform float Saturation;
+uniform vec4 Tint;
+void main() {
+    vec4 col = texture2D(Texture, uv);
+    vec3 c = col.rgb * Brightness;
+    c = (c - 0.5) * Contrast + 0.5;
+    float gray = dot(c, vec3(0.299, 0.587, 0.114));
+    c = mix(vec3(gray), c, Saturation) * Tint.rgb;
+    gl_FragColor = vec4(clamp(c, 0.0, 1.0), col.a);
+}
+"#;
+
+const VIGNETTE: &str = r#"#version 100
+precision mediump float;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+uniform float Radius;
+uniform float Softness;
+uniform float Intensity;
+void main() {
+    vec4 col = texture2D(Texture, uv);
+    float d = distance(uv, vec2(0.5)) * 1.41421;
+    float v = smoothstep(Radius, Radius - Softness, d);
+    gl_FragColor = vec4(col.rgb * mix(1.0, v, Intensity), col.a);
+}
+"#;
+
+const OVERLAY: &str = r#"#version 100
+precision mediump float;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+uniform vec4 Overlay;
+uniform float Amount;
+void main() {
+    vec4 col = texture2D(Texture, uv);
+    gl_FragColor = vec4(mix(col.rgb, Overlay.rgb, Amount * Overlay.a), col.a);
+}
+"#;
+
+const PIXELATE: &str = r#"#version 100
+precision mediump float;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+uniform vec2 Resolution;
+uniform float PixelSize;
+void main() {
+    vec2 block = PixelSize / Resolution;
+    gl_FragColor = texture2D(Texture, (floor(uv / block) + 0.5) * block);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effect_chain() {
+        let mut chain = EffectChain::new();
+        let scanlines = chain.push(Effect::scanlines());
+        let flash = chain.push(Effect::flash(WHITE, 0.5));
+        let fade = chain.push(Effect::fade_to_black(2.));
+        assert_eq!(chain.len(), 3);
+
+        if let Some(Effect::Scanlines { intensity, .. }) = chain.get_mut(scanlines) {
+            *intensity = 0.5;
+        }
+        assert_eq!(chain.get(scanlines).unwrap().uniforms()[0], ("Intensity", Uniform::Float(0.5)));
+
+        chain.update(0.25);
+        assert_eq!(chain.get(flash).unwrap().amount(), 0.5);
+        assert_eq!(chain.get(fade).unwrap().amount(), 0.125);
+        //闪屏结束后自动移除
+        chain.update(0.25);
+        assert!(chain.get(flash).is_none());
+        chain.update(10.);
+        assert_eq!(chain.get(fade).unwrap().amount(), 1.);
+        assert!(chain.get(fade).unwrap().is_done());
+        assert_eq!(Effect::fade_in(BLACK, 1.).amount(), 1.);
+
+        assert!(chain.remove(scanlines).is_some());
+        assert!(chain.remove(scanlines).is_none());
+        assert_eq!(chain.len(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{fmt::Write as _, path::Path, sync::OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+use macroquad::prelude::{FilterMode, Image, Texture2D};
+
+//内嵌资源: 编译时把资源文件打包进程序, 资源管理器加载文件时优先使用内嵌的文件.
+//调试版本优先从文件系统读取, 方便修改和热重载.
+//
+// 列出文件:
+//     mengine_quad::embed::set_embedded(mengine_quad::embed_assets!["static/Timmy.png", "static/assets.json"]);
+// 或在build.rs中打包整个目录:
+//     mengine_quad::embed::generate("static", &Path::new(&env::var("OUT_DIR").unwrap()).join("assets.rs")).unwrap();
+// 然后:
+//     mengine_quad::embed::set_embedded(include!(concat!(env!("OUT_DIR"), "/assets.rs")));
+
+/// 内嵌文件(路径, 内容)
+pub type EmbeddedFiles = &'static [(&'static str, &'static [u8])];
+
+static EMBEDDED: OnceLock<EmbeddedFiles> = OnceLock::new();
+
+/// 内嵌文件列表, 路径相对于Cargo.toml所在目录
+#[macro_export]
+macro_rules! embed_assets {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path)) as &[u8])),*]
+    };
+}
+
+/// 设置内嵌文件, 只能设置一次, 在加载资源前调用
+pub fn set_embedded(files: EmbeddedFiles) -> bool {
+    EMBEDDED.set(files).is_ok()
+}
+
+/// 统一路径格式: 使用'/'分隔, 去掉开头的"./"
+pub fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    path.trim_start_matches("./").to_string()
+}
+
+/// 查找内嵌文件
+pub fn embedded(path: &str) -> Option<&'static [u8]> {
+    let path = normalize(path);
+    EMBEDDED.get()?.iter().find(|(p, _)| *p == path).map(|(_, data)| *data)
+}
+
+/// 加载文件, 调试版本优先读取文件系统, 否则优先使用内嵌文件
+pub async fn load_file(path: &str) -> Result<Vec<u8>> {
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    if Path::new(path).exists() {
+        return macroquad::prelude::load_file(path).await.with_context(|| format!("文件加载失败: {}", path));
+    }
+    match embedded(path) {
+        Some(data) => Ok(data.to_vec()),
+        None => macroquad::prelude::load_file(path).await.with_context(|| format!("文件加载失败: {}", path)),
+    }
+}
+
+/// 同步读取文件, 顺序和load_file相同, 不能用于wasm
+pub fn read_file(path: &str) -> Result<Vec<u8>> {
+    #[cfg(debug_assertions)]
+    if Path::new(path).exists() {
+        return std::fs::read(path).with_context(|| format!("文件加载失败: {}", path));
+    }
+    match embedded(path) {
+        Some(data) => Ok(data.to_vec()),
+        None => std::fs::read(path).with_context(|| format!("文件加载失败: {}", path)),
+    }
+}
+
+/// 解码图片, 格式错误时返回错误而不是panic
+pub fn decode_image(data: &[u8], path: &str) -> Result<Image> {
+    std::panic::catch_unwind(|| Image::from_file_with_format(data, None)).map_err(|_| anyhow!("图片格式错误: {}", path))
+}
+
+pub async fn load_string(path: &str) -> Result<String> {
+    String::from_utf8(load_file(path).await?).map_err(|_| anyhow!("文件不是UTF-8文本: {}", path))
+}
+
+/// 加载图片, 使用最近邻过滤, 格式错误时返回错误
+pub async fn load_texture(path: &str) -> Result<Texture2D> {
+    let data = load_file(path).await?;
+    let texture = Texture2D::from_image(&decode_image(&data, path)?);
+    texture.set_filter(FilterMode::Nearest);
+    Ok(texture)
+}
+
+/// 生成打包目录中所有文件的代码, 在build.rs中调用, 生成的文件用include!引入
+pub fn generate(dir: &str, out_file: &Path) -> std::io::Result<()> {
+    let mut files = vec![];
+    collect_files(Path::new(dir), &mut files)?;
+    files.sort();
+    let mut code = String::from("&[\n");
+    for file in files {
+        let absolute = std::fs::canonicalize(&file)?;
+        let _ = writeln!(code, "    ({:?}, include_bytes!({:?}) as &[u8]),", normalize(&file.to_string_lossy()), absolute);
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+    code.push(']');
+    println!("cargo:rerun-if-changed={}", dir);
+    std::fs::write(out_file, code)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 测试共用的内嵌文件, set_embedded只能设置一次. broken.png只有PNG文件头
+#[cfg(test)]
+pub const TEST_FILES: EmbeddedFiles = &[
+    ("Cargo.toml", include_bytes!("../Cargo.toml")),
+    ("broken.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded() {
+        assert_eq!(normalize("./static\\Timmy.png"), "static/Timmy.png");
+        set_embedded(TEST_FILES);
+        assert!(embedded("./Cargo.toml").unwrap().starts_with(b"[package]"));
+        assert_eq!(embedded("static/Timmy.png"), None);
+
+        let out = std::env::temp_dir().join(format!("mengine_embed_{}.rs", std::process::id()));
+        generate("src/engine", &out).unwrap();
+        let code = std::fs::read_to_string(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert!(code.starts_with("&[\n    (\"src/engine/background.rs\", include_bytes!("));
+    }
+}
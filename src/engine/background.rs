@@ -1,20 +1,31 @@
-use macroquad::prelude::{Rect, vec2};
+use macroquad::prelude::{Rect, Vec2, vec2};
 
 use crate::*;
 
 pub struct ScrollingBackground {
     layers: Vec<BackgroundLayer>,
+    camera: Vec2,
+    /// 上次tick的时间戳ms
+    last_tick: Option<f64>,
+    paused: bool,
 }
 
 impl ScrollingBackground {
     pub fn new() -> ScrollingBackground {
-        ScrollingBackground { layers: vec![] }
+        ScrollingBackground { layers: vec![], camera: Vec2::ZERO, last_tick: None, paused: false }
     }
 
-    pub fn add_layer(&mut self, layer: BackgroundLayer) {
+    pub fn add_layer(&mut self, mut layer: BackgroundLayer) {
+        layer.set_camera(self.camera);
         self.layers.push(layer);
     }
 
+    /// 添加绘制在指定位置的图层
+    pub fn add_layer_at(&mut self, mut layer: BackgroundLayer, dest: Rect) {
+        layer.set_dest(Some(dest));
+        self.add_layer(layer);
+    }
+
     pub fn draw(&self) {
         for layer in &self.layers {
             layer.draw();
@@ -31,6 +42,67 @@ impl ScrollingBackground {
     pub fn layers(&mut self) -> &mut [BackgroundLayer]{
         &mut self.layers
     }
+
+    pub fn camera(&self) -> Vec2 {
+        self.camera
+    }
+
+    /// 设置摄像机位置, 各图层按视差系数滚动
+    pub fn set_camera(&mut self, camera: Vec2) {
+        self.camera = camera;
+        for layer in &mut self.layers {
+            layer.set_camera(camera);
+        }
+    }
+
+    /// 移动摄像机
+    pub fn move_camera(&mut self, delta: Vec2) {
+        self.set_camera(self.camera + delta);
+    }
+
+    /// 按距上次tick经过的时间自动滚动图层, 由GameEngine::update_backgrounds调用
+    pub fn tick(&mut self) {
+        let now = current_timestamp();
+        let elapsed = self.last_tick.map(|last| ((now - last) / 1000.) as f32).unwrap_or(0.);
+        self.last_tick = Some(now);
+        self.advance(elapsed);
+    }
+
+    /// 自动滚动经过elapsed秒
+    pub fn advance(&mut self, elapsed: f32) {
+        if self.paused {
+            return;
+        }
+        for layer in &mut self.layers {
+            layer.advance(elapsed);
+        }
+    }
+
+    /// 暂停所有图层的自动滚动
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// 在seconds秒内滚动delta像素, 各图层按视差系数缩放
+    pub fn scroll_by(&mut self, delta: Vec2, seconds: f32) {
+        for layer in &mut self.layers {
+            let parallax = layer.parallax();
+            layer.scroll_by(delta * parallax, seconds);
+        }
+    }
+
+    /// 是否有图层正在执行scroll_by
+    pub fn is_scrolling(&self) -> bool {
+        self.layers.iter().any(|layer| layer.is_scrolling())
+    }
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -41,10 +113,77 @@ pub enum ScrollDir {
     Left,
 }
 
+impl ScrollDir {
+    /// 视口在该方向上每次更新移动的单位向量(图层向上移动即视口向下滑动)
+    pub fn viewport_direction(&self) -> Vec2 {
+        match self {
+            ScrollDir::Up => vec2(0., 1.),
+            ScrollDir::Right => vec2(-1., 0.),
+            ScrollDir::Down => vec2(0., -1.),
+            ScrollDir::Left => vec2(1., 0.),
+        }
+    }
+}
+
+/// 图层重复方向
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Repeat {
+    Both,
+    /// 仅水平重复, 例如天空和地面条带
+    Horizontal,
+    /// 仅垂直重复
+    Vertical,
+}
+
+impl Repeat {
+    fn horizontal(&self) -> bool {
+        *self != Repeat::Vertical
+    }
+
+    fn vertical(&self) -> bool {
+        *self != Repeat::Horizontal
+    }
+}
+
+/// 图层绘制到目标矩形时的缩放方式
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum LayerScale {
+    /// 按原始大小绘制, 显示的区域和目标矩形一样大
+    None,
+    /// 视口拉伸到目标矩形
+    Stretch,
+    /// 保持比例, 位图高度缩放到目标矩形高度
+    FitHeight,
+    /// 保持比例, 位图宽度缩放到目标矩形宽度
+    FitWidth,
+}
+
 pub struct BackgroundLayer {
     viewport: Rect,
+    /// 绘制位置, None时绘制在(0, 0), 大小和视口相同
+    dest: Option<Rect>,
+    repeat: Repeat,
+    scale: LayerScale,
     speed: f32,
     direction: ScrollDir,
+    /// 视口每次更新移动的距离
+    velocity: Vec2,
+    /// 视差系数, 摄像机移动1像素时视口移动的距离
+    parallax: Vec2,
+    /// 摄像机在原点时视口的位置
+    origin: Vec2,
+    /// 按速度累计的滚动距离
+    scroll: Vec2,
+    camera: Vec2,
+    /// 自动滚动速度(像素/秒)
+    auto_velocity: Vec2,
+    /// 自动滚动目标速度
+    target_velocity: Vec2,
+    /// 加速度(像素/秒²), 0为立即达到目标速度
+    acceleration: f32,
+    paused: bool,
+    /// scroll_by剩余的(距离, 时间)
+    scroll_by: Option<(Vec2, f32)>,
     bitmap: Drawable,
 }
 
@@ -53,464 +192,211 @@ impl BackgroundLayer {
         BackgroundLayer {
             speed,
             direction,
+            velocity: direction.viewport_direction() * speed,
+            parallax: Vec2::ONE,
+            origin: viewport.point(),
+            scroll: Vec2::ZERO,
+            camera: Vec2::ZERO,
+            auto_velocity: Vec2::ZERO,
+            target_velocity: Vec2::ZERO,
+            acceleration: 0.,
+            paused: false,
+            scroll_by: None,
             bitmap,
             viewport,
+            dest: None,
+            repeat: Repeat::Both,
+            scale: LayerScale::None,
         }
     }
 
-    pub fn update(&mut self) {
-        match self.direction {
-            ScrollDir::Up => {
-                // Move the layer up (slide the viewport down)
-                self.viewport = self.viewport.offset(vec2(0., self.speed));
-                if self.viewport.top() > self.height() as f32 {
-                    self.viewport.move_to(vec2(self.viewport.left(), 0.));
-                }
-            }
+    /// 使用任意方向的速度创建图层
+    pub fn with_velocity(bitmap: Drawable, viewport: Rect, velocity: Vec2) -> BackgroundLayer {
+        let mut layer = Self::new(bitmap, viewport, 0., ScrollDir::Left);
+        layer.set_velocity(velocity);
+        layer
+    }
 
-            ScrollDir::Right => {
-                self.viewport = self.viewport.offset(vec2(-self.speed, 0.));
-                if self.viewport.right() < 0.0 {
-                    self.viewport.move_to(vec2(self.width() as f32 - (self.viewport.right() - self.viewport.left()), self.viewport.top()));
-                }
-            }
+    pub fn update(&mut self) {
+        self.scroll += self.velocity;
+        self.update_viewport();
+    }
 
-            ScrollDir::Down => {
-                self.viewport = self.viewport.offset(vec2(0., -self.speed));
-                if self.viewport.bottom() < 0.0 {
-                    self.viewport.move_to(vec2(self.viewport.left(), self.height() as f32 - (self.viewport.bottom() - self.viewport.top())))
-                }
-            }
+    /// 按经过的时间(秒)自动滚动
+    pub fn advance(&mut self, elapsed: f32) {
+        if self.paused || elapsed <= 0. {
+            return;
+        }
+        //向目标速度加速
+        let diff = self.target_velocity - self.auto_velocity;
+        let step = self.acceleration * elapsed;
+        if self.acceleration <= 0. || diff.length() <= step {
+            self.auto_velocity = self.target_velocity;
+        } else {
+            self.auto_velocity += diff.normalize() * step;
+        }
+        self.scroll += self.auto_velocity * elapsed;
 
-            ScrollDir::Left => {
-                // Move the layer left (slide the viewport right)
-                self.viewport = self.viewport.offset(vec2(self.speed, 0.));
-                if self.viewport.left() > self.width() as f32 {
-                    self.viewport.move_to(vec2(0., self.viewport.top()))
-                }
+        if let Some((remaining, time_left)) = self.scroll_by.take() {
+            if elapsed >= time_left {
+                self.scroll += remaining;
+            } else {
+                let delta = remaining * (elapsed / time_left);
+                self.scroll += delta;
+                self.scroll_by = Some((remaining - delta, time_left - elapsed));
             }
         }
+        self.update_viewport();
     }
 
-    pub fn draw(&self) {
-        let (x, y) = (0.0, 0.0);
-        //仅绘制通过视口看到的图层部分
-        if self.viewport.top() < 0.0 && self.viewport.left() < 0.0 {
-            //绘制分割视口，从上到下，从左到右
-            //绘制左上部分(对应图片右下部分)
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.width() as f32 + self.viewport.left(),
-                    self.height() as f32 + self.viewport.top(), //图像源左上角
-                    -self.viewport.left(),
-                    -self.viewport.top(),
-                )), //图像源宽高
-                Some(Rect::new(
-                    x,
-                    y, //目标绘制坐标
-                    -self.viewport.left(),
-                    -self.viewport.top(),
-                )),
-            );
-            //绘制右上部分(对应图片左下部分)
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.height() as f32 + self.viewport.top(),
-                    self.viewport.right(),
-                    -self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x - self.viewport.left(),
-                    y,
-                    self.viewport.right(),
-                    -self.viewport.top(),
-                )),
-            );
-
-            //绘制左下部分(对应图片右上部分)
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.width() as f32 + self.viewport.left(),
-                    0.0,
-                    -self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y - self.viewport.top(),
-                    -self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-            );
-            //绘制右下部分(对应图片左上部分)
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(0.0, 0.0, self.viewport.right(), self.viewport.bottom())),
-                Some(Rect::new(
-                    x - self.viewport.left(),
-                    y - self.viewport.top(),
-                    self.viewport.right(),
-                    self.viewport.bottom(),
-                )),
-            );
-        } else if self.viewport.top() < 0.0 && self.viewport.right() > self.width() as f32 {
-            //绘制拆开的视口，从顶部环绕到底部，从右侧环绕到左侧
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    self.height() as f32 + self.viewport.top(),
-                    self.width() as f32 - self.viewport.left(),
-                    -self.viewport.top(),
-                )),
-                Some(Rect::new(x, y, self.width() as f32 - self.viewport.left(), -self.viewport.top())),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.height() as f32 + self.viewport.top(),
-                    self.viewport.right() - self.width() as f32,
-                    -self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x + (self.width() as f32 - self.viewport.left()),
-                    y,
-                    self.viewport.right() - self.width() as f32,
-                    -self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    0.0,
-                    self.width() as f32 - self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y - self.viewport.top(),
-                    self.width() as f32 - self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    0.0,
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom(),
-                )),
-                Some(Rect::new(
-                    x + (self.width() as f32 - self.viewport.left()),
-                    y - self.viewport.top(),
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom(),
-                )),
-            );
-        } else if self.viewport.bottom() > self.height() as f32 && self.viewport.left() < 0.0 {
-            //绘制拆开的视口，从底部环绕到顶部，从左侧环绕到右侧
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.width() as f32 + self.viewport.left(),
-                    self.viewport.top(),
-                    -self.viewport.left(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-                Some(Rect::new(x, y, -self.viewport.left(), self.height() as f32 - self.viewport.top())),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.viewport.top(),
-                    self.viewport.right(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x - self.viewport.left(),
-                    y,
-                    self.viewport.right(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.width() as f32 + self.viewport.left(),
-                    0.0,
-                    -self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-                Some(Rect::new(
-                    x,
-                    y + (self.height() as f32 - self.viewport.top()),
-                    -self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    0.0,
-                    self.viewport.right(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-                Some(Rect::new(
-                    x - self.viewport.left(),
-                    y + (self.height() as f32 - self.viewport.top()),
-                    self.viewport.right(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-            );
-        } else if self.viewport.bottom() > self.height() as f32 && self.viewport.right() > self.width() as f32 {
-            //绘制所有窗口，从底部环绕到顶部，从右侧环绕到左侧
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    self.viewport.top(),
-                    self.width() as f32 - self.viewport.left(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y,
-                    self.width() as f32 - self.viewport.left(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.viewport.top(),
-                    self.viewport.right() - self.width() as f32,
-                    self.height() as f32 - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x + (self.width() as f32 - self.viewport.left()),
-                    y,
-                    self.viewport.right() - self.width() as f32,
-                    self.height() as f32 - self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    0.0,
-                    self.width() as f32 - self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-                Some(Rect::new(
-                    x,
-                    y + (self.height() as f32 - self.viewport.top()),
-                    self.width() as f32 - self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    0.0,
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-                Some(Rect::new(
-                    x + (self.width() as f32 - self.viewport.left()),
-                    y + (self.height() as f32 - self.viewport.top()),
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-            );
-        } else if self.viewport.top() < 0.0 {
-            //绘制拆开的视口，从顶部环绕到底部
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    self.height() as f32 + self.viewport.top(), //srcx, srcY
-                    self.viewport.right() - self.viewport.left(),
-                    -self.viewport.top(),
-                )), //width, height
-                Some(Rect::new(
-                    x,
-                    y, //destX, destY
-                    self.viewport.right() - self.viewport.left(),
-                    -self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    0.0, //srcX, srcY
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y - self.viewport.top(), //destX, destY
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom(),
-                )),
-            );
-        } else if self.viewport.right() > self.width() as f32 {
-            //绘制拆开的视口，从右侧环绕到左侧
-            let w = self.width() as f32 - self.viewport.left();
-            let h = self.viewport.bottom() - self.viewport.top();
-            if w > 0.0 && h > 0.0 {
-                graphics::draw_image(
-                    None,
-                    self.bitmap,
-                    Some(Rect::new(self.viewport.left(), self.viewport.top(), w, h)),
-                    Some(Rect::new(x, y, w, h)),
-                );
-            }
+    /// 设置自动滚动速度(像素/秒), 立即生效
+    pub fn set_auto_velocity(&mut self, velocity: Vec2) {
+        self.auto_velocity = velocity;
+        self.target_velocity = velocity;
+    }
+
+    pub fn auto_velocity(&self) -> Vec2 {
+        self.auto_velocity
+    }
 
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.viewport.top(),
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x + (self.width() as f32 - self.viewport.left()),
-                    y,
-                    self.viewport.right() - self.width() as f32,
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-            );
-        } else if self.viewport.bottom() > self.height() as f32 {
-            //绘制拆开的窗口，从底部环绕到顶部
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    self.viewport.top(),
-                    self.viewport.right() - self.viewport.left(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y,
-                    self.viewport.right() - self.viewport.left(),
-                    self.height() as f32 - self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    0.0,
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-                Some(Rect::new(
-                    x,
-                    y + (self.height() as f32 - self.viewport.top()),
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom() - self.height() as f32,
-                )),
-            );
-        } else if self.viewport.left() < 0.0 {
-            //绘制拆开的视口，从左侧环绕到右侧
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.width() as f32 + self.viewport.left(),
-                    self.viewport.top(),
-                    -self.viewport.left(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y,
-                    -self.viewport.left(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-            );
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    0.0,
-                    self.viewport.top(),
-                    self.viewport.right(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x - self.viewport.left(),
-                    y,
-                    self.viewport.right(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-            );
+    /// 以acceleration(像素/秒²)加速到目标速度
+    pub fn accelerate_to(&mut self, target: Vec2, acceleration: f32) {
+        self.target_velocity = target;
+        self.acceleration = acceleration;
+    }
+
+    /// 在seconds秒内匀速滚动delta像素, 会替换未完成的scroll_by
+    pub fn scroll_by(&mut self, delta: Vec2, seconds: f32) {
+        if seconds <= 0. {
+            self.scroll += delta;
+            self.update_viewport();
         } else {
-            //一次性绘制整个视口
-            graphics::draw_image(
-                None,
-                self.bitmap,
-                Some(Rect::new(
-                    self.viewport.left(),
-                    self.viewport.top(),
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-                Some(Rect::new(
-                    x,
-                    y,
-                    self.viewport.right() - self.viewport.left(),
-                    self.viewport.bottom() - self.viewport.top(),
-                )),
-            );
+            self.scroll_by = Some((delta, seconds));
+        }
+    }
+
+    pub fn is_scrolling(&self) -> bool {
+        self.scroll_by.is_some()
+    }
+
+    /// 暂停自动滚动
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// 根据原点、滚动距离和摄像机位置计算视口, 并环绕到位图范围内
+    fn update_viewport(&mut self) {
+        let mut position = self.origin + self.scroll + self.camera * self.parallax;
+        if self.width() > 0. && self.repeat.horizontal() {
+            position.x = position.x.rem_euclid(self.width());
+        }
+        if self.height() > 0. && self.repeat.vertical() {
+            position.y = position.y.rem_euclid(self.height());
+        }
+        self.viewport.move_to(position);
+    }
+
+    /// 绘制的目标矩形
+    pub fn dest_rect(&self) -> Rect {
+        self.dest.unwrap_or_else(|| Rect::new(0., 0., self.viewport.w, self.viewport.h))
+    }
+
+    /// 按缩放方式计算在位图上显示的区域
+    pub fn source_rect(&self) -> Rect {
+        let dest = self.dest_rect();
+        let size = match self.scale {
+            LayerScale::None => dest.size(),
+            LayerScale::Stretch => self.viewport.size(),
+            LayerScale::FitHeight if self.height() > 0. => vec2(dest.w * self.height() / dest.h, self.height()),
+            LayerScale::FitWidth if self.width() > 0. => vec2(self.width(), dest.h * self.width() / dest.w),
+            _ => dest.size(),
+        };
+        Rect::new(self.viewport.x, self.viewport.y, size.x, size.y)
+    }
+
+    pub fn draw(&self) {
+        //仅绘制通过视口看到的图层部分, 超出位图的部分环绕绘制
+        for (source, dest) in repeat_tiles(self.source_rect(), self.width(), self.height(), self.repeat, self.dest_rect()) {
+            graphics::draw_image(None, &self.bitmap, Some(source), Some(dest));
         }
     }
 
+    pub fn bitmap(&self) -> &Drawable {
+        &self.bitmap
+    }
+
+    pub fn bitmap_mut(&mut self) -> &mut Drawable {
+        &mut self.bitmap
+    }
+
+    pub fn set_dest(&mut self, dest: Option<Rect>) {
+        self.dest = dest;
+    }
+
+    pub fn dest(&self) -> Option<Rect> {
+        self.dest
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+        self.update_viewport();
+    }
+
+    pub fn set_scale(&mut self, scale: LayerScale) {
+        self.scale = scale;
+    }
+
     pub fn set_speed(&mut self, speed: f32) {
         self.speed = speed;
+        self.velocity = self.direction.viewport_direction() * speed;
     }
 
     pub fn set_direction(&mut self, direction: ScrollDir) {
         self.direction = direction;
+        self.velocity = direction.viewport_direction() * self.speed;
+    }
+
+    /// 设置视口每次更新移动的距离, 会覆盖set_speed和set_direction设置的速度
+    pub fn set_velocity(&mut self, velocity: Vec2) {
+        self.velocity = velocity;
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        self.velocity
+    }
+
+    /// 视差系数: 1.0随摄像机同步滚动, 小于1.0的远景滚动更慢, 0.0不随摄像机滚动
+    pub fn set_parallax(&mut self, parallax: Vec2) {
+        self.parallax = parallax;
+        self.update_viewport();
+    }
+
+    pub fn parallax(&self) -> Vec2 {
+        self.parallax
+    }
+
+    pub fn set_camera(&mut self, camera: Vec2) {
+        self.camera = camera;
+        self.update_viewport();
     }
 
     pub fn set_viewport(&mut self, viewport: Rect) {
         self.viewport = viewport;
+        //以新视口为原点, 保留摄像机位置
+        self.origin = viewport.point() - self.camera * self.parallax;
+        self.scroll = Vec2::ZERO;
+    }
+
+    pub fn viewport(&self) -> Rect {
+        self.viewport
     }
 
     pub fn width(&self) -> f32 {
@@ -521,3 +407,205 @@ impl BackgroundLayer {
         self.bitmap.height()
     }
 }
+
+/// 将一维区间[start, start + len)按周期size拆分为(源起点, 目标偏移, 长度), 不重复时只保留位图内的部分
+fn wrap_spans(start: f32, len: f32, size: f32, repeat: bool) -> Vec<(f32, f32, f32)> {
+    if size <= 0. || len <= 0. {
+        return vec![];
+    }
+    if !repeat {
+        let (from, to) = (start.max(0.), (start + len).min(size));
+        return if to > from { vec![(from, from - start, to - from)] } else { vec![] };
+    }
+    let mut spans = vec![];
+    let mut source = start.rem_euclid(size);
+    let mut offset = 0.;
+    while offset < len {
+        let span = (size - source).min(len - offset);
+        spans.push((source, offset, span));
+        offset += span;
+        source = 0.;
+    }
+    spans
+}
+
+/// 计算覆盖视口所需的位图分块(源矩形, 目标矩形), 位图在两个方向上无限重复.
+/// 视口可以在任意位置, 也可以大于位图; dest为视口左上角的绘制位置
+pub fn wrap_tiles(viewport: Rect, width: f32, height: f32, dest: Vec2) -> Vec<(Rect, Rect)> {
+    repeat_tiles(viewport, width, height, Repeat::Both, Rect::new(dest.x, dest.y, viewport.w, viewport.h))
+}
+
+/// 按重复方向计算覆盖视口的位图分块, 视口缩放到目标矩形
+pub fn repeat_tiles(viewport: Rect, width: f32, height: f32, repeat: Repeat, dest: Rect) -> Vec<(Rect, Rect)> {
+    if viewport.w <= 0. || viewport.h <= 0. {
+        return vec![];
+    }
+    let (kx, ky) = (dest.w / viewport.w, dest.h / viewport.h);
+    let columns = wrap_spans(viewport.x, viewport.w, width, repeat.horizontal());
+    let rows = wrap_spans(viewport.y, viewport.h, height, repeat.vertical());
+    let mut tiles = vec![];
+    for &(sy, dy, h) in &rows {
+        for &(sx, dx, w) in &columns {
+            tiles.push((
+                Rect::new(sx, sy, w, h),
+                Rect::new(dest.x + dx * kx, dest.y + dy * ky, w * kx, h * ky),
+            ));
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::{vec2, Rect, WHITE};
+
+    use super::*;
+
+    fn bitmap() -> Drawable {
+        Drawable::Rectangle { width: 100., height: 80., color: WHITE }
+    }
+
+    #[test]
+    fn test_velocity_and_parallax() {
+        let mut background = ScrollingBackground::new();
+        background.add_layer(BackgroundLayer::with_velocity(bitmap(), Rect::new(10., 10., 50., 50.), vec2(30., -25.)));
+        let mut far = BackgroundLayer::new(bitmap(), Rect::new(0., 0., 50., 50.), 0., ScrollDir::Left);
+        far.set_parallax(vec2(0.5, 0.));
+        background.add_layer(far);
+
+        //速度移动后环绕到位图范围内
+        background.update();
+        background.update();
+        assert_eq!(background.layers()[0].viewport(), Rect::new(70., 40., 50., 50.));
+        //摄像机按视差移动
+        background.move_camera(vec2(-40., 20.));
+        assert_eq!(background.layers()[0].viewport(), Rect::new(30., 60., 50., 50.));
+        assert_eq!(background.layers()[1].viewport(), Rect::new(80., 0., 50., 50.));
+    }
+
+    fn r(x: f32, y: f32, w: f32, h: f32) -> Rect {
+        Rect::new(x, y, w, h)
+    }
+
+    #[test]
+    fn test_auto_scroll() {
+        let mut layer = BackgroundLayer::new(bitmap(), Rect::new(0., 0., 50., 50.), 0., ScrollDir::Left);
+        layer.set_repeat(Repeat::Vertical);
+        layer.set_auto_velocity(vec2(10., 0.));
+        layer.advance(0.5);
+        assert_eq!(layer.viewport().point(), vec2(5., 0.));
+        //暂停时不滚动
+        layer.pause();
+        layer.advance(1.);
+        assert_eq!(layer.viewport().point(), vec2(5., 0.));
+        layer.resume();
+        //加速到目标速度: 1秒内从10加速到30, 然后保持30
+        layer.accelerate_to(vec2(40., 0.), 20.);
+        layer.advance(1.);
+        assert_eq!(layer.auto_velocity(), vec2(30., 0.));
+        assert_eq!(layer.viewport().point(), vec2(35., 0.));
+        layer.advance(1.);
+        assert_eq!(layer.auto_velocity(), vec2(40., 0.));
+        layer.set_auto_velocity(Vec2::ZERO);
+
+        //在2秒内滚动100像素
+        let mut background = ScrollingBackground::new();
+        let mut far = BackgroundLayer::new(bitmap(), Rect::new(0., 0., 50., 50.), 0., ScrollDir::Left);
+        far.set_parallax(vec2(0.5, 0.5));
+        far.set_repeat(Repeat::Vertical);
+        background.add_layer(far);
+        background.scroll_by(vec2(100., 0.), 2.);
+        background.advance(0.5);
+        assert_eq!(background.layers()[0].viewport().point(), vec2(12.5, 0.));
+        assert!(background.is_scrolling());
+        background.advance(5.);
+        assert_eq!(background.layers()[0].viewport().point(), vec2(50., 0.));
+        assert!(!background.is_scrolling());
+    }
+
+    #[test]
+    fn test_wrap_tiles_inside() {
+        //视口完全在位图内, 一次绘制
+        assert_eq!(wrap_tiles(r(10., 20., 30., 40.), 100., 80., Vec2::ZERO), vec![
+            (r(10., 20., 30., 40.), r(0., 0., 30., 40.)),
+        ]);
+    }
+
+    #[test]
+    fn test_wrap_tiles_split() {
+        //从右侧环绕到左侧
+        assert_eq!(wrap_tiles(r(80., 0., 40., 10.), 100., 80., Vec2::ZERO), vec![
+            (r(80., 0., 20., 10.), r(0., 0., 20., 10.)),
+            (r(0., 0., 20., 10.), r(20., 0., 20., 10.)),
+        ]);
+        //从底部环绕到顶部
+        assert_eq!(wrap_tiles(r(0., 70., 10., 20.), 100., 80., Vec2::ZERO), vec![
+            (r(0., 70., 10., 10.), r(0., 0., 10., 10.)),
+            (r(0., 0., 10., 10.), r(0., 10., 10., 10.)),
+        ]);
+        //左上角为负数: 从顶部环绕到底部, 从左侧环绕到右侧
+        assert_eq!(wrap_tiles(r(-5., -10., 20., 30.), 100., 80., Vec2::ZERO), vec![
+            (r(95., 70., 5., 10.), r(0., 0., 5., 10.)),
+            (r(0., 70., 15., 10.), r(5., 0., 15., 10.)),
+            (r(95., 0., 5., 20.), r(0., 10., 5., 20.)),
+            (r(0., 0., 15., 20.), r(5., 10., 15., 20.)),
+        ]);
+        //偏移超过一个位图周期
+        assert_eq!(wrap_tiles(r(-290., 250., 10., 10.), 100., 80., vec2(3., 4.)), vec![
+            (r(10., 10., 10., 10.), r(3., 4., 10., 10.)),
+        ]);
+    }
+
+    #[test]
+    fn test_wrap_tiles_larger_than_bitmap() {
+        //视口大于位图时重复平铺
+        assert_eq!(wrap_tiles(r(50., 0., 250., 80.), 100., 80., Vec2::ZERO), vec![
+            (r(50., 0., 50., 80.), r(0., 0., 50., 80.)),
+            (r(0., 0., 100., 80.), r(50., 0., 100., 80.)),
+            (r(0., 0., 100., 80.), r(150., 0., 100., 80.)),
+        ]);
+        assert_eq!(wrap_tiles(r(0., 60., 10., 200.), 100., 80., Vec2::ZERO), vec![
+            (r(0., 60., 10., 20.), r(0., 0., 10., 20.)),
+            (r(0., 0., 10., 80.), r(0., 20., 10., 80.)),
+            (r(0., 0., 10., 80.), r(0., 100., 10., 80.)),
+            (r(0., 0., 10., 20.), r(0., 180., 10., 20.)),
+        ]);
+        //空位图不绘制
+        assert!(wrap_tiles(r(0., 0., 10., 10.), 0., 0., Vec2::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_repeat_tiles() {
+        //仅水平重复: 垂直方向只绘制位图内的部分
+        assert_eq!(repeat_tiles(r(90., -10., 20., 40.), 100., 20., Repeat::Horizontal, r(0., 100., 20., 40.)), vec![
+            (r(90., 0., 10., 20.), r(0., 110., 10., 20.)),
+            (r(0., 0., 10., 20.), r(10., 110., 10., 20.)),
+        ]);
+        //仅垂直重复, 放大2倍
+        assert_eq!(repeat_tiles(r(-5., 15., 10., 10.), 100., 20., Repeat::Vertical, r(10., 10., 20., 20.)), vec![
+            (r(0., 15., 5., 5.), r(20., 10., 10., 10.)),
+            (r(0., 0., 5., 5.), r(20., 20., 10., 10.)),
+        ]);
+    }
+
+    #[test]
+    fn test_layer_dest_and_scale() {
+        let mut layer = BackgroundLayer::new(bitmap(), Rect::new(0., 0., 50., 50.), 0., ScrollDir::Left);
+        assert_eq!(layer.dest_rect(), r(0., 0., 50., 50.));
+        //天空条带: 位图高度80缩放到目标高度40
+        layer.set_dest(Some(r(0., 0., 256., 40.)));
+        layer.set_scale(LayerScale::FitHeight);
+        layer.set_repeat(Repeat::Horizontal);
+        assert_eq!(layer.source_rect(), r(0., 0., 512., 80.));
+        layer.set_scale(LayerScale::FitWidth);
+        assert_eq!(layer.source_rect(), r(0., 0., 100., 15.625));
+        layer.set_scale(LayerScale::Stretch);
+        assert_eq!(layer.source_rect(), r(0., 0., 50., 50.));
+        layer.set_scale(LayerScale::None);
+        assert_eq!(layer.source_rect(), r(0., 0., 256., 40.));
+        //仅水平重复时垂直方向不环绕
+        layer.set_velocity(vec2(0., -30.));
+        layer.update();
+        assert_eq!(layer.viewport().point(), vec2(0., -30.));
+    }
+}
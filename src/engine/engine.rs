@@ -1,4 +1,11 @@
-use super::sprite::{Sprite, SA_ADDSPRITE, SA_KILL};
+use std::collections::HashMap;
+
+use macroquad::prelude::{Rect, Vec2};
+
+use crate::{assets::Reload, audio::{AudioBackend, AudioManager, SoundHandle}};
+
+use super::background::ScrollingBackground;
+use super::sprite::{Resource, Sprite, WorldTransform, SpriteID, SA_ADDSPRITE, SA_KILL, collision_rect, rects_touch};
 
 //GameEngine 负责创建游戏窗口、绘制和更新精灵
 pub trait GameEngine {
@@ -7,6 +14,17 @@ pub trait GameEngine {
     fn sprite_dying(&mut self, sprite_dying_id: usize);
     fn sprite_collision(&mut self, sprite_hitter_id: usize, sprite_hittee_id: usize) -> bool;
 
+    /// 注册到引擎的滚动背景, 在update_sprites时按经过的时间自动滚动
+    fn backgrounds_mut(&mut self) -> Vec<&mut ScrollingBackground> {
+        vec![]
+    }
+
+    fn update_backgrounds(&mut self) {
+        for background in self.backgrounds_mut() {
+            background.tick();
+        }
+    }
+
     fn add_sprite(&mut self, sprite: Sprite) {
         let sprites = self.sprites_mut();
         if sprites.len() > 0 {
@@ -24,12 +42,17 @@ pub trait GameEngine {
 
     fn draw_sprites(&mut self) {
         //绘制所有的精灵
-        for sprite in self.sprites() {
-            sprite.draw();
+        for (i, sprite) in self.sprites().iter().enumerate() {
+            if sprite.attached() {
+                sprite.draw_world(&self.world_transform(i));
+            } else {
+                sprite.draw();
+            }
         }
     }
 
     fn update_sprites(&mut self) {
+        self.update_backgrounds();
         let sprites_num = self.sprites().len();
         //更新所有精灵
         let mut sprites_to_kill: Vec<String> = vec![];
@@ -61,6 +84,23 @@ pub trait GameEngine {
             }
         }
 
+        //级联杀死附着在死亡精灵上的子精灵
+        let mut i = 0;
+        while i < sprites_to_kill.len() {
+            let children: Vec<SpriteID> = self.sprites().iter()
+                .filter(|s| s.attached() && s.parent() == Some(&sprites_to_kill[i]))
+                .map(|s| s.id().to_string())
+                .filter(|id| !sprites_to_kill.contains(id))
+                .collect();
+            for child_id in children {
+                if let Some(index) = self.index_of_sprite(&child_id) {
+                    self.sprite_dying(index);
+                }
+                sprites_to_kill.push(child_id);
+            }
+            i += 1;
+        }
+
         //删除死亡的精灵
         for sprite_id in sprites_to_kill {
             self.sprites_mut().retain(|ref s| s.id() != sprite_id);
@@ -69,31 +109,139 @@ pub trait GameEngine {
 
     fn check_sprite_collision(&mut self, test_sprite_id: usize) -> bool {
         //检查精灵是否和其他精灵相撞
-        let sprites = self.sprites_mut();
-        let test_sprite = &sprites[test_sprite_id];
-        for i in 0..sprites.len() {
-            //不检查精灵自己
-            if i == test_sprite_id {
+        let test_rect = collision_rect(&self.world_rect(test_sprite_id));
+        for i in 0..self.sprites().len() {
+            //不检查精灵自己, 也不检查附着在一起的精灵
+            if i == test_sprite_id || self.is_attached_to(i, test_sprite_id) || self.is_attached_to(test_sprite_id, i) {
                 continue;
             }
-            if test_sprite.test_collison(sprites[i].position()) {
+            if rects_touch(&test_rect, &self.world_rect(i)) {
                 return self.sprite_collision(i, test_sprite_id);
             }
         }
         return false;
     }
 
+    /// 精灵的父精灵链(不含自身), 从直接父精灵开始
+    fn ancestors(&self, index: usize) -> Vec<usize> {
+        let sprites = self.sprites();
+        let mut chain = vec![];
+        let mut current = index;
+        while sprites[current].attached() {
+            match sprites[current].parent().and_then(|pid| self.index_of_sprite(pid)) {
+                //防止循环引用
+                Some(parent) if parent != index && !chain.contains(&parent) => {
+                    chain.push(parent);
+                    current = parent;
+                }
+                _ => break,
+            }
+        }
+        chain
+    }
+
+    /// 精灵是否(直接或间接)附着在另一个精灵上
+    fn is_attached_to(&self, index: usize, ancestor: usize) -> bool {
+        self.ancestors(index).contains(&ancestor)
+    }
+
+    /// 精灵的世界变换, 附着的子精灵会叠加所有父精灵的变换
+    fn world_transform(&self, index: usize) -> WorldTransform {
+        let sprites = self.sprites();
+        let mut world: Option<(WorldTransform, Vec2)> = None;
+        for i in self.ancestors(index).into_iter().rev().chain(std::iter::once(index)) {
+            let transform = sprites[i].world_transform_in(world.as_ref().map(|(w, o)| (w, *o)));
+            world = Some((transform, sprites[i].origin()));
+        }
+        world.map(|(w, _)| w).unwrap_or_default()
+    }
+
+    /// 精灵在世界空间中的矩形(不考虑旋转)
+    fn world_rect(&self, index: usize) -> Rect {
+        self.sprites()[index].world_rect(&self.world_transform(index))
+    }
+
+    /// 精灵左上角的世界坐标(不考虑旋转)
+    fn world_position(&self, id: &str) -> Option<Vec2> {
+        self.index_of_sprite(id).map(|i| self.world_rect(i).point())
+    }
+
+    /// 将精灵附着到父精灵上(None为解除附着), 保持精灵的世界位置、旋转和缩放不变.
+    /// 父精灵不存在或者父精灵附着在该精灵上时返回false
+    fn attach_sprite(&mut self, id: &str, parent_id: Option<&str>) -> bool {
+        let index = match self.index_of_sprite(id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let world = self.world_transform(index);
+        let parent = match parent_id {
+            Some(parent_id) => match self.index_of_sprite(parent_id) {
+                Some(parent) if parent != index && !self.is_attached_to(parent, index) => {
+                    Some((self.world_transform(parent), self.sprites()[parent].origin()))
+                }
+                _ => return false,
+            },
+            None => None,
+        };
+        let sprite = &mut self.sprites_mut()[index];
+        sprite.set_world_transform(&world, parent.as_ref().map(|(w, o)| (w, *o)));
+        sprite.set_parent(parent_id.map(|id| id.to_string()));
+        sprite.set_attached(parent_id.is_some());
+        true
+    }
+
+    /// 按精灵相对于听者(例如摄像机中心)的位置更新声音发射器的增益和声像, 每次更新时调用.
+    /// 多个精灵循环播放同一声音时使用最响的一个
+    fn update_audio_emitters<B: AudioBackend>(&mut self, audio: &mut AudioManager<B>, listener: Vec2) where Self: Sized {
+        let mut loops: HashMap<SoundHandle, (f32, f32)> = HashMap::new();
+        for i in 0..self.sprites().len() {
+            if self.sprites()[i].emitter().is_none() {
+                continue;
+            }
+            let position = self.world_rect(i).center();
+            let emitter = self.sprites_mut()[i].emitter_mut().unwrap();
+            let (gain, pan) = emitter.spatial(listener, position);
+            if emitter.take_pending() {
+                audio.play_spatial(emitter.sound, gain, pan);
+            }
+            if emitter.looped {
+                let entry = loops.entry(emitter.sound).or_insert((gain, pan));
+                if gain > entry.0 {
+                    *entry = (gain, pan);
+                }
+            }
+        }
+        audio.update_spatial_loops(loops);
+    }
+
+    /// 替换精灵和背景中热重载的图片和动画帧, 保留精灵状态
+    fn apply_reload(&mut self, reload: &Reload) {
+        if reload.is_empty() {
+            return;
+        }
+        for sprite in self.sprites_mut() {
+            match sprite.resource_mut() {
+                Resource::Static(drawable) => reload.apply_drawable(drawable),
+                Resource::Animation(animation) => reload.apply_animation(animation),
+            };
+        }
+        for background in self.backgrounds_mut() {
+            for layer in background.layers() {
+                reload.apply_drawable(layer.bitmap_mut());
+            }
+        }
+    }
+
     fn clean_up_sprites(&mut self) {
         self.sprites_mut().clear();
     }
 
     fn is_point_in_sprite(&mut self, x: f32, y: f32) -> Option<&Sprite> {
-        for sprite in self.sprites() {
-            if !sprite.hidden() && sprite.is_point_inside(x, y) {
-                return Some(sprite);
-            }
-        }
-        None
+        let index = (0..self.sprites().len()).find(|&i| {
+            let sprite = &self.sprites()[i];
+            !sprite.hidden() && sprite.is_point_inside(&self.world_transform(i), x, y)
+        })?;
+        Some(&self.sprites()[index])
     }
 
     fn get_sprite(&mut self, id: &str) -> Option<&mut Sprite> {
@@ -146,3 +294,89 @@ pub trait GameEngine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::{vec2, Rect, WHITE};
+
+    use super::GameEngine;
+    use crate::engine::{Resource, Sprite, BA_NONE};
+    use crate::{Drawable, Point};
+
+    struct TestGame {
+        sprites: Vec<Sprite>,
+        dying: Vec<String>,
+    }
+
+    impl GameEngine for TestGame {
+        fn sprites_mut(&mut self) -> &mut Vec<Sprite> {
+            &mut self.sprites
+        }
+        fn sprites(&self) -> &Vec<Sprite> {
+            &self.sprites
+        }
+        fn sprite_dying(&mut self, sprite_dying_id: usize) {
+            self.dying.push(self.sprites[sprite_dying_id].id().to_string());
+        }
+        fn sprite_collision(&mut self, _: usize, _: usize) -> bool {
+            false
+        }
+    }
+
+    fn sprite(id: &str, x: f32, y: f32) -> Sprite {
+        Sprite::new(
+            id.to_string(),
+            id.to_string(),
+            Resource::Static(Drawable::None),
+            Point { x, y },
+            Point { x: 0., y: 0. },
+            0,
+            Rect::new(-1000., -1000., 2000., 2000.),
+            BA_NONE,
+        )
+    }
+
+    #[test]
+    fn test_scene_graph() {
+        let mut game = TestGame { sprites: vec![sprite("tank", 100., 50.), sprite("turret", 110., 50.)], dying: vec![] };
+        assert!(game.attach_sprite("turret", Some("tank")));
+        //附着后世界位置不变, 本地位置相对于父精灵
+        assert_eq!(game.world_position("turret"), Some(vec2(110., 50.)));
+        assert_eq!(game.sprite_mut("turret").position().point(), vec2(10., 0.));
+        //父精灵移动和旋转带动子精灵
+        game.sprite_mut("tank").set_position(200., 50.);
+        game.sprite_mut("tank").set_rotation(std::f32::consts::FRAC_PI_2);
+        let pos = game.world_position("turret").unwrap();
+        assert!((pos - vec2(200., 60.)).length() < 0.001);
+        //不能形成循环
+        assert!(!game.attach_sprite("tank", Some("turret")));
+        //父精灵死亡时级联杀死子精灵
+        game.sprite_mut("tank").kill();
+        game.update_sprites();
+        assert!(game.sprites.is_empty());
+        assert_eq!(game.dying, vec!["tank", "turret"]);
+    }
+
+    #[test]
+    fn test_attached_bounds() {
+        let bounds = Rect::new(0., 0., 600., 450.);
+        let mut tank = Sprite::from_bitmap("tank".to_string(), "tank".to_string(), Resource::Static(Drawable::Rectangle { width: 20., height: 10., color: WHITE }), bounds);
+        tank.set_position(100., 50.);
+        let mut turret = Sprite::from_bitmap("turret".to_string(), "turret".to_string(), Resource::Static(Drawable::Rectangle { width: 8., height: 4., color: WHITE }), bounds);
+        turret.set_position(80., 40.);
+        let mut game = TestGame { sprites: vec![tank, turret], dying: vec![] };
+        assert!(game.attach_sprite("turret", Some("tank")));
+        game.sprite_mut("turret").set_velocity(1., 0.);
+        //默认BA_STOP, 负的本地位置不会被限制在边界内
+        game.update_sprites();
+        assert_eq!(game.sprite_mut("turret").position().point(), vec2(-19., -10.));
+        assert_eq!(game.sprite_mut("turret").velocity().x, 1.);
+        assert_eq!(game.world_position("turret"), Some(vec2(81., 40.)));
+        //点和碰撞检查使用世界位置
+        assert_eq!(game.is_point_in_sprite(83., 42.).map(|s| s.id().to_string()), Some("turret".to_string()));
+        let world = game.world_transform(1);
+        assert!(game.sprites[1].test_collison(&world, &Rect::new(80., 40., 4., 4.)));
+        assert!(!game.sprites[1].test_collison(&world, &Rect::new(-19., -10., 2., 2.)));
+        assert_eq!(game.sprites[1].collision(&world).center(), vec2(85., 42.));
+    }
+}
@@ -1,5 +1,7 @@
-use macroquad::prelude::{Rect, vec2};
+use macroquad::prelude::{Rect, vec2, Color, Vec2};
 use crate::*;
+use crate::graphics::Transform;
+use crate::audio::AudioEmitter;
 use std::cmp;
 
 //Sprite主要代码
@@ -63,6 +65,62 @@ impl Resource {
     }
 }
 
+/// 精灵在世界空间中的变换, 以精灵的旋转中心为基准
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldTransform {
+    /// 旋转中心的世界坐标
+    pub pivot: Vec2,
+    /// 旋转角度(弧度)
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+impl Default for WorldTransform {
+    fn default() -> Self {
+        WorldTransform {
+            pivot: Vec2::ZERO,
+            rotation: 0.,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl WorldTransform {
+    /// 相对于旋转中心的本地坐标转换为世界坐标
+    pub fn transform_point(&self, local: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        let p = local * self.scale;
+        self.pivot + vec2(p.x * cos - p.y * sin, p.x * sin + p.y * cos)
+    }
+
+    /// 世界坐标转换为相对于旋转中心的本地坐标
+    pub fn inverse_transform_point(&self, world: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        let d = world - self.pivot;
+        let p = vec2(d.x * cos + d.y * sin, -d.x * sin + d.y * cos);
+        vec2(div_or_zero(p.x, self.scale.x), div_or_zero(p.y, self.scale.y))
+    }
+}
+
+fn div_or_zero(a: f32, b: f32) -> f32 {
+    if b == 0. { 0. } else { a / b }
+}
+
+/// 矩形是否接触(包含边缘)
+pub fn rects_touch(a: &Rect, b: &Rect) -> bool {
+    a.left() <= b.right()
+        && b.left() <= a.right()
+        && a.top() <= b.bottom()
+        && b.top() <= a.bottom()
+}
+
+/// 碰撞矩形为精灵矩形向内收缩1/12
+pub fn collision_rect(rect: &Rect) -> Rect {
+    let x_shrink = (rect.left() - rect.right()) / 12.0;
+    let y_shrink = (rect.top() - rect.bottom()) / 12.0;
+    inflate(rect, x_shrink, y_shrink)
+}
+
 pub struct Sprite {
     id: SpriteID,
     name: String,
@@ -70,6 +128,7 @@ pub struct Sprite {
     score: i32,
     lives: i32,
     parent_id: Option<SpriteID>,
+    attached: bool,
     killer_id: Option<SpriteID>,
     rel_id: Option<SpriteID>,
     rel_id2: Option<SpriteID>,
@@ -80,10 +139,11 @@ pub struct Sprite {
     bounds: Rect,
     velocity: Point,
     z_order: i32,
-    collision: Rect,
     bounds_action: BOUNDSACTION,
     hidden: bool,
     dying: bool,
+    transform: Transform,
+    emitter: Option<AudioEmitter>,
 }
 
 impl Sprite {
@@ -97,12 +157,13 @@ impl Sprite {
         bounds: Rect,
         bounds_action: BOUNDSACTION,
     ) -> Sprite {
-        let mut sprite = Sprite {
+        Sprite {
             id,
             type_name: String::new(),
             lives: 0,
             score: 0,
             parent_id: None,
+            attached: false,
             killer_id: None,
             rel_id: None,
             rel_id2: None,
@@ -122,10 +183,9 @@ impl Sprite {
             bounds_action: bounds_action,
             hidden: false,
             dying: false,
-            collision: Rect::default(),
-        };
-        sprite.calc_collision_rect();
-        sprite
+            transform: Transform::default(),
+            emitter: None,
+        }
     }
 
     pub fn from_bitmap(id: String, name: String, resource: Resource, bounds: Rect) -> Sprite {
@@ -166,11 +226,54 @@ impl Sprite {
         )
     }
 
-    fn calc_collision_rect(&mut self) {
-        let x_shrink = (self.position.left() - self.position.right()) / 12.0;
-        let y_shrink = (self.position.top() - self.position.bottom()) / 12.0;
-        self.collision = self.position;
-        self.collision = inflate(&self.collision, x_shrink, y_shrink);
+    /// 旋转中心, 相对于精灵左上角
+    pub fn origin(&self) -> Vec2 {
+        self.transform.origin_in(&self.position)
+    }
+
+    /// 计算精灵的世界变换, 附着的子精灵需传入父精灵的世界变换
+    pub fn world_transform_in(&self, parent: Option<(&WorldTransform, Vec2)>) -> WorldTransform {
+        let scale = vec2(self.transform.scale.0, self.transform.scale.1);
+        //精灵在父空间中的旋转中心
+        let pivot = vec2(self.position.x, self.position.y) + self.origin()
+            + vec2(self.transform.translate.0, self.transform.translate.1);
+        match parent {
+            Some((parent, parent_origin)) if self.attached => WorldTransform {
+                pivot: parent.transform_point(pivot - parent_origin),
+                rotation: parent.rotation + self.transform.rotate,
+                scale: parent.scale * scale,
+            },
+            _ => WorldTransform { pivot, rotation: self.transform.rotate, scale },
+        }
+    }
+
+    /// 按世界变换重新设置本地位置、旋转和缩放, 使精灵在新的父空间中保持世界位置不变
+    pub fn set_world_transform(&mut self, world: &WorldTransform, parent: Option<(&WorldTransform, Vec2)>) {
+        let (pivot, rotation, scale) = match parent {
+            Some((parent, parent_origin)) => (
+                parent.inverse_transform_point(world.pivot) + parent_origin,
+                world.rotation - parent.rotation,
+                vec2(div_or_zero(world.scale.x, parent.scale.x), div_or_zero(world.scale.y, parent.scale.y)),
+            ),
+            None => (world.pivot, world.rotation, world.scale),
+        };
+        self.transform.rotate = rotation;
+        self.transform.scale = (scale.x, scale.y);
+        let top_left = pivot - self.origin() - vec2(self.transform.translate.0, self.transform.translate.1);
+        self.set_position(top_left.x, top_left.y);
+    }
+
+    /// 世界空间中的精灵矩形(不考虑旋转)
+    pub fn world_rect(&self, world: &WorldTransform) -> Rect {
+        let origin = self.origin();
+        let size = vec2(self.position.w, self.position.h) * world.scale.abs();
+        let top_left = world.pivot - origin * world.scale.abs();
+        Rect::new(top_left.x, top_left.y, size.x, size.y)
+    }
+
+    /// 缩放后的精灵矩形(不考虑旋转)
+    pub fn bounding_rect(&self) -> Rect {
+        self.transform.apply(self.position)
     }
 
     //-----------------------------------------------------------------
@@ -202,6 +305,12 @@ impl Sprite {
         bounds_size.x = self.bounds.right() - self.bounds.left();
         bounds_size.y = self.bounds.bottom() - self.bounds.top();
 
+        //附着的子精灵位置相对于父精灵, 不检查边界
+        if self.attached() {
+            self.set_position_point(&new_position);
+            return SA_NONE;
+        }
+
         // Check the bounds
         // Wrap?
         if self.bounds_action == BA_WRAP {
@@ -296,18 +405,31 @@ impl Sprite {
     }
 
     pub fn draw(&self) {
+        self.draw_world(&self.world_transform_in(None));
+    }
+
+    /// 按世界变换绘制精灵
+    pub fn draw_world(&self, world: &WorldTransform) {
         // Draw the sprite if it isn't hidden
         if !self.hidden {
             // Draw the appropriate frame, if necessary
+            let origin = self.origin();
             let dest = Rect::new(
-                self.position.left() as f32,
-                self.position.top() as f32,
+                world.pivot.x - origin.x,
+                world.pivot.y - origin.y,
                 self.resource.width(),
                 self.resource.height(),
             );
+            let transform = Transform {
+                rotate: world.rotation,
+                translate: (0., 0.),
+                scale: (world.scale.x, world.scale.y),
+                origin: Some((origin.x, origin.y)),
+                ..self.transform
+            };
             match &self.resource {
-                Resource::Animation(anim) => anim.draw(None, dest),
-                Resource::Static(image) => graphics::draw_image(None, image.clone(), None, Some(dest)),
+                Resource::Animation(anim) => anim.draw(Some(transform), dest),
+                Resource::Static(image) => graphics::draw_image(Some(transform), image, None, Some(dest)),
             };
         }
     }
@@ -330,29 +452,79 @@ impl Sprite {
         let dx = position.x - self.position.left();
         let dy = position.y - self.position.top();
         self.position = self.position.offset(vec2(dx, dy));
-        self.calc_collision_rect();
     }
 
     pub fn set_position(&mut self, x: f32, y: f32) {
         let x = x - self.position.left();
         let y = y - self.position.top();
         self.position = self.position.offset(vec2(x, y));
-        self.calc_collision_rect();
     }
 
     pub fn set_position_rect(&mut self, position: Rect) {
         self.position = position;
     }
 
-    pub fn test_collison(&self, test: &Rect) -> bool {
-        self.collision.left() <= test.right()
-            && test.left() <= self.collision.right()
-            && self.collision.top() <= test.bottom()
-            && test.top() <= self.collision.bottom()
+    /// 按世界变换检查碰撞, world为GameEngine::world_transform的结果
+    pub fn test_collison(&self, world: &WorldTransform, test: &Rect) -> bool {
+        rects_touch(&self.collision(world), test)
     }
 
-    pub fn is_point_inside(&self, x: f32, y: f32) -> bool {
-        self.position.contains(vec2(x, y))
+    /// 按世界变换检查点是否在精灵内
+    pub fn is_point_inside(&self, world: &WorldTransform, x: f32, y: f32) -> bool {
+        self.world_rect(world).contains(vec2(x, y))
+    }
+
+    /// 世界坐标中的碰撞矩形, 和test_collison使用的相同
+    pub fn collision(&self, world: &WorldTransform) -> Rect {
+        collision_rect(&self.world_rect(world))
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    /// 旋转角度(弧度)
+    pub fn rotation(&self) -> f32 {
+        self.transform.rotate
+    }
+
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.transform.rotate = rotation;
+    }
+
+    pub fn scale(&self) -> (f32, f32) {
+        self.transform.scale
+    }
+
+    pub fn set_scale(&mut self, x: f32, y: f32) {
+        self.transform.scale = (x, y);
+    }
+
+    /// 旋转和缩放中心, 相对于精灵左上角, None为精灵中心
+    pub fn set_origin(&mut self, origin: Option<(f32, f32)>) {
+        self.transform.origin = origin;
+    }
+
+    pub fn set_flip(&mut self, flip_x: bool, flip_y: bool) {
+        self.transform.flip_x = flip_x;
+        self.transform.flip_y = flip_y;
+    }
+
+    pub fn set_tint(&mut self, tint: Color) {
+        self.transform.tint = tint;
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.transform.alpha
+    }
+
+    /// 不透明度 0.0~1.0
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.transform.alpha = alpha;
     }
 
     pub fn height(&self) -> f32 {
@@ -456,6 +628,36 @@ impl Sprite {
         self.parent_id = parent_id;
     }
 
+    /// 是否附着在父精灵上, 附着时位置、旋转和缩放相对于父精灵
+    pub fn attached(&self) -> bool {
+        self.attached && self.parent_id.is_some()
+    }
+
+    /// 设置是否附着在父精灵上, 不改变本地位置. 需要保持世界位置时使用GameEngine::attach_sprite
+    pub fn set_attached(&mut self, attached: bool){
+        self.attached = attached;
+    }
+
+    /// 精灵的声音发射器, 由GameEngine::update_audio_emitters按精灵位置更新
+    pub fn emitter(&self) -> Option<&AudioEmitter> {
+        self.emitter.as_ref()
+    }
+
+    pub fn emitter_mut(&mut self) -> Option<&mut AudioEmitter> {
+        self.emitter.as_mut()
+    }
+
+    pub fn set_emitter(&mut self, emitter: Option<AudioEmitter>) {
+        self.emitter = emitter;
+    }
+
+    /// 在精灵位置播放一次发射器的声音
+    pub fn play_sound(&mut self) {
+        if let Some(emitter) = self.emitter.as_mut() {
+            emitter.play();
+        }
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -1,55 +1,433 @@
 use macroquad::prelude::*;
 
-use crate::Drawable;
+use crate::{text::{self, TextLayout, TextStyle, TextTransform}, Drawable, SubImage};
 
+/// 绘制变换: 旋转、缩放、翻转、着色和透明度
+#[derive(Clone, Copy, Debug)]
 pub struct Transform {
+    /// 旋转角度(弧度)
     pub rotate: f32,
     pub translate: (f32, f32),
+    /// 缩放(x, y), 可以不等比
+    pub scale: (f32, f32),
+    /// 旋转和缩放中心, 相对于目标矩形左上角, None为目标矩形中心
+    pub origin: Option<(f32, f32)>,
+    /// 水平翻转
+    pub flip_x: bool,
+    /// 垂直翻转
+    pub flip_y: bool,
+    /// 着色
+    pub tint: Color,
+    /// 不透明度 0.0~1.0
+    pub alpha: f32,
 }
+
 impl Default for Transform {
     fn default() -> Self {
         Transform {
             rotate: 0.0,
             translate: (0.0, 0.0),
+            scale: (1.0, 1.0),
+            origin: None,
+            flip_x: false,
+            flip_y: false,
+            tint: WHITE,
+            alpha: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    /// 变换中心(相对于目标矩形左上角)
+    pub fn origin_in(&self, dest: &Rect) -> Vec2 {
+        match self.origin {
+            Some((x, y)) => vec2(x, y),
+            None => vec2(dest.w / 2., dest.h / 2.),
+        }
+    }
+
+    /// 计算缩放和平移后的目标矩形(不考虑旋转)
+    pub fn apply(&self, dest: Rect) -> Rect {
+        let origin = self.origin_in(&dest);
+        let (sx, sy) = (self.scale.0.abs(), self.scale.1.abs());
+        Rect::new(
+            dest.x + origin.x * (1. - sx) + self.translate.0,
+            dest.y + origin.y * (1. - sy) + self.translate.1,
+            dest.w * sx,
+            dest.h * sy,
+        )
+    }
+
+    /// 着色乘以不透明度后的最终颜色
+    pub fn color(&self) -> Color {
+        Color::new(self.tint.r, self.tint.g, self.tint.b, self.tint.a * self.alpha.clamp(0., 1.))
+    }
+}
+
+/// 矢量图形, 坐标相对于图形左上角(0, 0)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Circle { radius: f32, color: Color },
+    /// 凸多边形
+    Polygon { points: Vec<Vec2>, color: Color },
+    Line { from: Vec2, to: Vec2, thickness: f32, color: Color },
+}
+
+impl Shape {
+    pub fn width(&self) -> f32 {
+        match self {
+            Shape::Circle { radius, .. } => radius * 2.,
+            Shape::Polygon { points, .. } => points.iter().fold(0f32, |w, p| w.max(p.x)),
+            Shape::Line { from, to, .. } => from.x.max(to.x),
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        match self {
+            Shape::Circle { radius, .. } => radius * 2.,
+            Shape::Polygon { points, .. } => points.iter().fold(0f32, |h, p| h.max(p.y)),
+            Shape::Line { from, to, .. } => from.y.max(to.y),
+        }
+    }
+}
+
+/// 按样式绘制的一段文字, 不自动换行, 可以使用富文本标记
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRun {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+impl TextRun {
+    pub fn new<T: Into<String>>(text: T, style: TextStyle) -> TextRun {
+        TextRun { text: text.into(), style }
+    }
+
+    pub fn layout(&self) -> TextLayout {
+        text::layout_text(&self.text, None, &self.style)
+    }
+
+    pub fn measure(&self) -> Vec2 {
+        text::measure(&self.text, None, &self.style)
+    }
+}
+
+/// 九宫格面板: 四角保持原样, 边和中心拉伸(或平铺)到目标大小
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NineSlice {
+    pub texture: Texture2D,
+    /// 纹理中使用的区域, None为整个纹理
+    pub region: Option<Rect>,
+    /// 边框宽度(左, 上, 右, 下)
+    pub insets: (f32, f32, f32, f32),
+    /// 默认绘制大小
+    pub size: Vec2,
+    /// 边和中心平铺而不是拉伸
+    pub tiled: bool,
+}
+
+impl NineSlice {
+    pub fn new(texture: Texture2D, insets: (f32, f32, f32, f32), size: Vec2) -> NineSlice {
+        NineSlice { texture, region: None, insets, size, tiled: false }
+    }
+
+    /// 使用纹理子图创建, 子图不是纹理时返回None
+    pub fn from_sub_image(image: &SubImage, insets: (f32, f32, f32, f32), size: Vec2) -> Option<NineSlice> {
+        match image.image() {
+            Drawable::Texture2D(texture) => Some(NineSlice {
+                texture: *texture,
+                region: Some(image.region()),
+                insets,
+                size,
+                tiled: false,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn set_tiled(&mut self, tiled: bool) {
+        self.tiled = tiled;
+    }
+
+    pub fn region(&self) -> Rect {
+        self.region.unwrap_or_else(|| Rect::new(0., 0., self.texture.width(), self.texture.height()))
+    }
+
+    /// 绘制到目标矩形
+    pub fn draw(&self, transform: Option<Transform>, dest: Rect) {
+        draw_image(transform, &Drawable::NineSlice(*self), None, Some(dest));
+    }
+
+    /// 计算各部分的(源矩形, 目标矩形), 平铺时边和中心会拆分为多块
+    pub fn slices(&self, dest: Rect) -> Vec<(Rect, Rect)> {
+        let src = self.region();
+        let (l, t, r, b) = self.insets;
+        //目标太小时按比例压缩边框
+        let kx = if l + r > dest.w && l + r > 0. { dest.w / (l + r) } else { 1. };
+        let ky = if t + b > dest.h && t + b > 0. { dest.h / (t + b) } else { 1. };
+        let columns = [
+            (src.x, l, dest.x, l * kx),
+            (src.x + l, src.w - l - r, dest.x + l * kx, dest.w - (l + r) * kx),
+            (src.right() - r, r, dest.right() - r * kx, r * kx),
+        ];
+        let rows = [
+            (src.y, t, dest.y, t * ky),
+            (src.y + t, src.h - t - b, dest.y + t * ky, dest.h - (t + b) * ky),
+            (src.bottom() - b, b, dest.bottom() - b * ky, b * ky),
+        ];
+        let mut slices = vec![];
+        for (row, &row_span) in rows.iter().enumerate() {
+            for (col, &col_span) in columns.iter().enumerate() {
+                for (sy, sh, dy, dh) in spans(row_span, self.tiled && row == 1) {
+                    for (sx, sw, dx, dw) in spans(col_span, self.tiled && col == 1) {
+                        slices.push((Rect::new(sx, sy, sw, sh), Rect::new(dx, dy, dw, dh)));
+                    }
+                }
+            }
         }
+        slices
     }
 }
 
+impl From<NineSlice> for Drawable {
+    fn from(panel: NineSlice) -> Self {
+        Drawable::NineSlice(panel)
+    }
+}
+
+/// 将一段(源起点, 源长度, 目标起点, 目标长度)拆分为平铺的多段, 最后一段按剩余长度裁剪源
+fn spans(span: (f32, f32, f32, f32), tiled: bool) -> Vec<(f32, f32, f32, f32)> {
+    let (src, src_len, dst, dst_len) = span;
+    if src_len <= 0. || dst_len <= 0. {
+        return vec![];
+    }
+    if !tiled {
+        return vec![span];
+    }
+    let mut spans = vec![];
+    let mut offset = 0.;
+    while offset < dst_len {
+        let len = src_len.min(dst_len - offset);
+        spans.push((src, len, dst + offset, len));
+        offset += len;
+    }
+    spans
+}
+
+/// 将内容的本地坐标映射到屏幕坐标(缩放、翻转、旋转)
+struct Placement {
+    rect: Rect,
+    size: Vec2,
+    flip_x: bool,
+    flip_y: bool,
+    rotation: f32,
+    pivot: Vec2,
+}
+
+impl Placement {
+    fn new(transform: Option<Transform>, size: Vec2, dest: Rect) -> Placement {
+        let transform = transform.unwrap_or_default();
+        let origin = transform.origin_in(&dest);
+        Placement {
+            rect: transform.apply(dest),
+            size,
+            flip_x: transform.flip_x ^ (transform.scale.0 < 0.),
+            flip_y: transform.flip_y ^ (transform.scale.1 < 0.),
+            rotation: transform.rotate,
+            pivot: vec2(dest.x + origin.x + transform.translate.0, dest.y + origin.y + transform.translate.1),
+        }
+    }
+
+    fn rotate(&self, p: Vec2) -> Vec2 {
+        if self.rotation == 0. {
+            return p;
+        }
+        let (sin, cos) = self.rotation.sin_cos();
+        let d = p - self.pivot;
+        self.pivot + vec2(d.x * cos - d.y * sin, d.x * sin + d.y * cos)
+    }
+
+    fn map(&self, p: Vec2) -> Vec2 {
+        let mut u = vec2(
+            if self.size.x == 0. { 0. } else { p.x / self.size.x },
+            if self.size.y == 0. { 0. } else { p.y / self.size.y },
+        );
+        if self.flip_x {
+            u.x = 1. - u.x;
+        }
+        if self.flip_y {
+            u.y = 1. - u.y;
+        }
+        self.rotate(self.rect.point() + u * self.rect.size())
+    }
+
+    /// 子矩形(屏幕坐标, 未旋转)按翻转镜像
+    fn mirror(&self, r: Rect) -> Rect {
+        let mut r = r;
+        if self.flip_x {
+            r.x = self.rect.x + self.rect.right() - r.right();
+        }
+        if self.flip_y {
+            r.y = self.rect.y + self.rect.bottom() - r.bottom();
+        }
+        r
+    }
+
+    fn fill(&self, points: &[Vec2], color: Color) {
+        //凸多边形按扇形拆分为三角形
+        let points: Vec<Vec2> = points.iter().map(|p| self.map(*p)).collect();
+        for i in 1..points.len().saturating_sub(1) {
+            draw_triangle(points[0], points[i], points[i + 1], color);
+        }
+    }
+}
+
+/// 颜色乘以变换的着色和透明度
+fn tint(color: Color, transform: &Option<Transform>) -> Color {
+    match transform {
+        Some(transform) => {
+            let t = transform.color();
+            Color::new(color.r * t.r, color.g * t.g, color.b * t.b, color.a * t.a)
+        }
+        None => color,
+    }
+}
+
+fn draw_texture_region(texture: Texture2D, transform: Option<Transform>, source: Option<Rect>, dest: Rect, flip_y: bool) {
+    let placement = Placement::new(transform, dest.size(), dest);
+    let mut params = DrawTextureParams {
+        source,
+        dest_size: Some(placement.rect.size()),
+        flip_x: placement.flip_x,
+        flip_y: placement.flip_y ^ flip_y,
+        ..Default::default()
+    };
+    if placement.rotation != 0. {
+        params.rotation = placement.rotation;
+        params.pivot = Some(placement.pivot);
+    }
+    draw_texture_ex(texture, placement.rect.x, placement.rect.y, tint(WHITE, &transform), params);
+}
+
 pub fn draw_image(
     transform: Option<Transform>,
-    image: Drawable,
+    image: &Drawable,
     source: Option<Rect>,
     dest: Option<Rect>,
 ) {
+    let size = vec2(image.width(), image.height());
+    //未指定目标矩形时按原始大小绘制在(0, 0)
+    let dest = dest.unwrap_or_else(|| {
+        let size = source.map(|s| s.size()).unwrap_or(size);
+        Rect::new(0., 0., size.x, size.y)
+    });
     match image{
-        Drawable::None => (),
-        Drawable::Texture2D(image) => {
-            if source.is_some() || dest.is_some(){
-                let mut params = DrawTextureParams::default();
-        
-                params.source = source;
-                let mut x = 0.;
-                let mut y = 0.;
-                if let Some(dest) = dest{
-                    x = dest.x;
-                    y = dest.y;
-                    params.dest_size = Some(vec2(dest.w, dest.h));
+        Drawable::None | Drawable::Blank { .. } => (),
+        Drawable::Texture2D(image) => draw_texture_region(*image, transform, source, dest, false),
+        //渲染目标纹理是上下颠倒的
+        Drawable::RenderTarget(image) => draw_texture_region(*image, transform, source, dest, true),
+        Drawable::NineSlice(panel) => {
+            let placement = Placement::new(transform, size, dest);
+            for (src, dst) in panel.slices(placement.rect) {
+                let dst = placement.mirror(dst);
+                let mut params = DrawTextureParams {
+                    source: Some(src),
+                    dest_size: Some(dst.size()),
+                    flip_x: placement.flip_x,
+                    flip_y: placement.flip_y,
+                    ..Default::default()
+                };
+                if placement.rotation != 0. {
+                    params.rotation = placement.rotation;
+                    params.pivot = Some(placement.pivot);
                 }
-                if let Some(transform) = transform{
-                    if transform.rotate != 0.{
-                        params.rotation = transform.rotate;
-                    }
-                    x += transform.translate.0;
-                    y += transform.translate.1; 
+                draw_texture_ex(panel.texture, dst.x, dst.y, tint(WHITE, &transform), params);
+            }
+        }
+        Drawable::Rectangle { width, height, color } => {
+            let placement = Placement::new(transform, size, dest);
+            placement.fill(&[vec2(0., 0.), vec2(*width, 0.), vec2(*width, *height), vec2(0., *height)], tint(*color, &transform));
+        }
+        Drawable::Shape(shape) => {
+            let placement = Placement::new(transform, size, dest);
+            match shape {
+                Shape::Circle { radius, color } => {
+                    let points: Vec<Vec2> = (0..32).map(|i| {
+                        let a = i as f32 / 32. * std::f32::consts::TAU;
+                        vec2(radius + radius * a.cos(), radius + radius * a.sin())
+                    }).collect();
+                    placement.fill(&points, tint(*color, &transform));
+                }
+                Shape::Polygon { points, color } => placement.fill(points, tint(*color, &transform)),
+                Shape::Line { from, to, thickness, color } => {
+                    let (a, b) = (placement.map(*from), placement.map(*to));
+                    draw_line(a.x, a.y, b.x, b.y, *thickness, tint(*color, &transform));
                 }
-                draw_texture_ex(image, x, y, WHITE, params);
-            }else{
-                draw_texture(image, 0., 0., WHITE);
             }
         }
+        Drawable::Text(run) => {
+            let placement = Placement::new(transform, size, dest);
+            let layout = run.layout();
+            let scale = vec2(
+                if layout.width > 0. { placement.rect.w / layout.width } else { 1. },
+                if layout.height > 0. { placement.rect.h / layout.height } else { 1. },
+            );
+            text::draw_layout_ex(&layout, placement.rect.x, placement.rect.y, &run.style.font, &TextTransform {
+                scale,
+                rotation: placement.rotation,
+                pivot: placement.pivot,
+                tint: tint(WHITE, &transform),
+            });
+        }
     }
 }
 
-pub fn draw_text(cotnent: &str, x: f32, y: f32, color: &[u8; 4], font_size: f32) {
-    macroquad::prelude::draw_text(cotnent, x, y, font_size, Color::from_rgba(color[0], color[1], color[2], color[3]));
-}
\ No newline at end of file
+#[test]
+fn test_transform_apply(){
+    let dest = Rect::new(10., 20., 40., 30.);
+    //默认以中心缩放
+    let t = Transform{ scale: (2., 0.5), ..Default::default() };
+    assert_eq!(t.apply(dest), Rect::new(-10., 27.5, 80., 15.));
+    //以左上角缩放, 负缩放只影响翻转
+    let t = Transform{ scale: (-2., 2.), origin: Some((0., 0.)), translate: (1., 1.), ..Default::default() };
+    assert_eq!(t.apply(dest), Rect::new(11., 21., 80., 60.));
+    let t = Transform{ alpha: 0.5, ..Default::default() };
+    assert_eq!(t.color().a, 0.5);
+}
+
+#[test]
+fn test_nine_slice(){
+    let mut panel = NineSlice{
+        texture: Texture2D::empty(),
+        region: Some(Rect::new(0., 0., 30., 30.)),
+        insets: (10., 10., 10., 10.),
+        size: vec2(50., 40.),
+        tiled: false,
+    };
+    let slices = panel.slices(Rect::new(5., 5., 50., 40.));
+    assert_eq!(slices.len(), 9);
+    //左上角保持原样
+    assert_eq!(slices[0], (Rect::new(0., 0., 10., 10.), Rect::new(5., 5., 10., 10.)));
+    //中心拉伸
+    assert_eq!(slices[4], (Rect::new(10., 10., 10., 10.), Rect::new(15., 15., 30., 20.)));
+    //右下角
+    assert_eq!(slices[8], (Rect::new(20., 20., 10., 10.), Rect::new(45., 35., 10., 10.)));
+
+    //平铺: 中间列30 = 10 + 10 + 10, 中间行20 = 10 + 10
+    panel.set_tiled(true);
+    let slices = panel.slices(Rect::new(0., 0., 50., 40.));
+    assert_eq!(slices.len(), 4 + 3 * 2 + 2 * 2 + 3 * 2);
+    //最后一块按剩余长度裁剪
+    let slices = panel.slices(Rect::new(0., 0., 45., 30.));
+    assert!(slices.contains(&(Rect::new(10., 0., 5., 10.), Rect::new(30., 0., 5., 10.))));
+
+    //目标小于边框时压缩边框
+    panel.set_tiled(false);
+    let slices = panel.slices(Rect::new(0., 0., 10., 10.));
+    assert_eq!(slices, vec![
+        (Rect::new(0., 0., 10., 10.), Rect::new(0., 0., 5., 5.)),
+        (Rect::new(20., 0., 10., 10.), Rect::new(5., 0., 5., 5.)),
+        (Rect::new(0., 20., 10., 10.), Rect::new(0., 5., 5., 5.)),
+        (Rect::new(20., 20., 10., 10.), Rect::new(5., 5., 5., 5.)),
+    ]);
+}
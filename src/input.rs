@@ -0,0 +1,485 @@
+use std::{collections::{HashMap, HashSet}, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::prelude::{KeyCode, MouseButton};
+
+use crate::{json::Json, Event};
+
+//输入映射: 把按键、鼠标按键和手柄按键/摇杆绑定到命名的动作和轴, 游戏中只查询动作, 不直接检查按键
+//
+//     let mut input = InputMap::new();
+//     input.bind("jump", Binding::Key(KeyCode::Space));
+//     input.bind("jump", Binding::Gamepad(GamepadButton::South));
+//     input.bind_axis("move_x", AxisBinding::keys(KeyCode::Left, KeyCode::Right));
+//     input.bind_axis("move_x", AxisBinding::stick(GamepadAxis::LeftStickX));
+//
+// 在State::event中调用input.event(event), 在State::update结束时调用input.tick()
+// macroquad不支持手柄, 手柄状态需要通过gamepad_button/gamepad_axis传入(例如来自gilrs)
+
+/// 手柄按键, 按位置命名(South对应Xbox的A键)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+const GAMEPAD_BUTTONS: [GamepadButton; 16] = {
+    use GamepadButton::*;
+    [South, East, West, North, LeftShoulder, RightShoulder, LeftTrigger, RightTrigger, Select, Start, LeftStick, RightStick, DPadUp, DPadDown, DPadLeft, DPadRight]
+};
+
+/// 手柄摇杆和扳机, 摇杆范围-1~1, 扳机范围0~1
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+const GAMEPAD_AXES: [GamepadAxis; 6] = {
+    use GamepadAxis::*;
+    [LeftStickX, LeftStickY, RightStickX, RightStickY, LeftTrigger, RightTrigger]
+};
+
+const MOUSE_BUTTONS: [MouseButton; 3] = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+const KEYS: [KeyCode; 120] = {
+    use KeyCode::*;
+    [
+        Space, Apostrophe, Comma, Minus, Period, Slash, Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7,
+        Key8, Key9, Semicolon, Equal, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W,
+        X, Y, Z, LeftBracket, Backslash, RightBracket, GraveAccent, World1, World2, Escape, Enter, Tab,
+        Backspace, Insert, Delete, Right, Left, Down, Up, PageUp, PageDown, Home, End, CapsLock,
+        ScrollLock, NumLock, PrintScreen, Pause, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13,
+        F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, F25, Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7,
+        Kp8, Kp9, KpDecimal, KpDivide, KpMultiply, KpSubtract, KpAdd, KpEnter, KpEqual, LeftShift,
+        LeftControl, LeftAlt, LeftSuper, RightShift, RightControl, RightAlt, RightSuper, Menu
+    ]
+};
+
+/// 动作绑定的输入
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// 格式: "Key:Space", "Mouse:Left", "Gamepad:South"
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "Key:{:?}", key),
+            Binding::Mouse(button) => write!(f, "Mouse:{:?}", button),
+            Binding::Gamepad(button) => write!(f, "Gamepad:{:?}", button),
+        }
+    }
+}
+
+/// 按Debug输出的名字查找
+fn find<T: Copy + fmt::Debug>(items: &[T], name: &str) -> Option<T> {
+    items.iter().copied().find(|item| format!("{:?}", item) == name)
+}
+
+/// 按名字查找按键, 名字和KeyCode的变体相同
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    find(&KEYS, name)
+}
+
+pub fn parse_mouse_button(name: &str) -> Option<MouseButton> {
+    find(&MOUSE_BUTTONS, name)
+}
+
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Binding> {
+        let binding = match s.split_once(':') {
+            Some(("Key", name)) => parse_key(name).map(Binding::Key),
+            Some(("Mouse", name)) => parse_mouse_button(name).map(Binding::Mouse),
+            Some(("Gamepad", name)) => find(&GAMEPAD_BUTTONS, name).map(Binding::Gamepad),
+            _ => None,
+        };
+        binding.ok_or_else(|| anyhow!("无效的绑定: {}", s))
+    }
+}
+
+/// 轴绑定的输入
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AxisBinding {
+    /// 两个按键, 按下negative为-1, 按下positive为1
+    Digital { negative: Binding, positive: Binding },
+    /// 手柄摇杆或扳机
+    Analog { axis: GamepadAxis, invert: bool },
+}
+
+impl AxisBinding {
+    pub fn keys(negative: KeyCode, positive: KeyCode) -> AxisBinding {
+        AxisBinding::Digital { negative: Binding::Key(negative), positive: Binding::Key(positive) }
+    }
+
+    pub fn stick(axis: GamepadAxis) -> AxisBinding {
+        AxisBinding::Analog { axis, invert: false }
+    }
+
+    pub fn inverted(axis: GamepadAxis) -> AxisBinding {
+        AxisBinding::Analog { axis, invert: true }
+    }
+}
+
+/// 格式: "Key:Left/Key:Right", "Axis:LeftStickX", 反转的轴为"-Axis:LeftStickY"
+impl fmt::Display for AxisBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AxisBinding::Digital { negative, positive } => write!(f, "{}/{}", negative, positive),
+            AxisBinding::Analog { axis, invert } => write!(f, "{}Axis:{:?}", if *invert { "-" } else { "" }, axis),
+        }
+    }
+}
+
+impl FromStr for AxisBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AxisBinding> {
+        if let Some((negative, positive)) = s.split_once('/') {
+            return Ok(AxisBinding::Digital { negative: negative.parse()?, positive: positive.parse()? });
+        }
+        let (invert, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        name.strip_prefix("Axis:")
+            .and_then(|name| find(&GAMEPAD_AXES, name))
+            .map(|axis| AxisBinding::Analog { axis, invert })
+            .ok_or_else(|| anyhow!("无效的轴绑定: {}", s))
+    }
+}
+
+/// 轴: 所有绑定的值相加后限制在-1~1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Axis {
+    pub bindings: Vec<AxisBinding>,
+    /// 摇杆死区, 绝对值小于死区的输入视为0, 其余部分重新映射到0~1
+    pub dead_zone: f32,
+}
+
+impl Default for Axis {
+    fn default() -> Self {
+        Self { bindings: vec![], dead_zone: 0.2 }
+    }
+}
+
+/// 应用死区
+pub fn dead_zone(value: f32, dead_zone: f32) -> f32 {
+    let magnitude = value.abs();
+    if magnitude <= dead_zone || dead_zone >= 1. {
+        0.
+    } else {
+        value.signum() * ((magnitude - dead_zone) / (1. - dead_zone)).min(1.)
+    }
+}
+
+#[derive(Default)]
+pub struct InputMap {
+    actions: HashMap<String, Vec<Binding>>,
+    axes: HashMap<String, Axis>,
+    down: HashSet<Binding>,
+    //上次tick之后按下/松开的输入
+    pressed: HashSet<Binding>,
+    released: HashSet<Binding>,
+    last_pressed: Option<Binding>,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+}
+
+impl InputMap {
+    pub fn new() -> InputMap {
+        InputMap::default()
+    }
+
+    /// 给动作添加绑定
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.actions.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    /// 替换动作的所有绑定
+    pub fn rebind(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.actions.insert(action.to_string(), bindings);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// 给轴添加绑定
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        let axis = self.axes.entry(axis.to_string()).or_default();
+        if !axis.bindings.contains(&binding) {
+            axis.bindings.push(binding);
+        }
+    }
+
+    pub fn unbind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        if let Some(axis) = self.axes.get_mut(axis) {
+            axis.bindings.retain(|b| *b != binding);
+        }
+    }
+
+    pub fn set_dead_zone(&mut self, axis: &str, dead_zone: f32) {
+        self.axes.entry(axis.to_string()).or_default().dead_zone = dead_zone;
+    }
+
+    pub fn axis_binding(&self, axis: &str) -> Option<&Axis> {
+        self.axes.get(axis)
+    }
+
+    /// 处理State::event收到的事件
+    pub fn event(&mut self, event: Event) {
+        match event {
+            Event::KeyDown { key, repeat: false, .. } => self.set(Binding::Key(key), true),
+            Event::KeyUp { key, .. } => self.set(Binding::Key(key), false),
+            Event::MouseDown(button, _, _) => self.set(Binding::Mouse(button), true),
+            Event::MouseUp(button, _, _) => self.set(Binding::Mouse(button), false),
+            _ => (),
+        }
+    }
+
+    pub fn gamepad_button(&mut self, button: GamepadButton, down: bool) {
+        self.set(Binding::Gamepad(button), down);
+    }
+
+    pub fn gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.gamepad_axes.insert(axis, value.clamp(-1., 1.));
+    }
+
+    fn set(&mut self, binding: Binding, down: bool) {
+        if down {
+            if self.down.insert(binding) {
+                self.pressed.insert(binding);
+                self.last_pressed = Some(binding);
+            }
+        } else if self.down.remove(&binding) {
+            self.released.insert(binding);
+        }
+    }
+
+    /// 松开所有输入, 例如打开暂停菜单时
+    pub fn release_all(&mut self) {
+        self.released.extend(self.down.drain());
+        self.gamepad_axes.clear();
+    }
+
+    /// 每次update结束时调用, 清除按下/松开状态
+    pub fn tick(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.last_pressed = None;
+    }
+
+    /// 上次tick之后按下的输入, 用于在设置界面中修改绑定
+    pub fn last_pressed(&self) -> Option<Binding> {
+        self.last_pressed
+    }
+
+    /// 动作在上次tick之后被按下
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| self.pressed.contains(b))
+    }
+
+    /// 动作处于按下状态
+    pub fn is_down(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| self.down.contains(b))
+    }
+
+    /// 动作在上次tick之后被松开, 并且没有其他绑定按下
+    pub fn is_released(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| self.released.contains(b)) && !self.is_down(action)
+    }
+
+    /// 轴的值, 范围-1~1
+    pub fn axis(&self, axis: &str) -> f32 {
+        let axis = match self.axes.get(axis) {
+            Some(axis) => axis,
+            None => return 0.,
+        };
+        let value: f32 = axis.bindings.iter().map(|binding| match binding {
+            AxisBinding::Digital { negative, positive } => {
+                self.down.contains(positive) as i32 as f32 - self.down.contains(negative) as i32 as f32
+            }
+            AxisBinding::Analog { axis: gamepad_axis, invert } => {
+                let value = dead_zone(self.gamepad_axes.get(gamepad_axis).copied().unwrap_or(0.), axis.dead_zone);
+                if *invert { -value } else { value }
+            }
+        }).sum();
+        value.clamp(-1., 1.)
+    }
+
+    /// 保存绑定配置
+    /// {"actions": {"jump": ["Key:Space", "Gamepad:South"]}, "axes": {"move_x": {"dead_zone": 0.2, "bindings": ["Key:Left/Key:Right", "Axis:LeftStickX"]}}}
+    pub fn profile(&self) -> String {
+        let mut actions: Vec<_> = self.actions.iter().collect();
+        actions.sort_by(|a, b| a.0.cmp(b.0));
+        let mut axes: Vec<_> = self.axes.iter().collect();
+        axes.sort_by(|a, b| a.0.cmp(b.0));
+        Json::Object(vec![
+            ("actions".to_string(), Json::Object(actions.into_iter().map(|(name, bindings)| {
+                (name.clone(), Json::Array(bindings.iter().map(|b| Json::String(b.to_string())).collect()))
+            }).collect())),
+            ("axes".to_string(), Json::Object(axes.into_iter().map(|(name, axis)| {
+                (name.clone(), Json::Object(vec![
+                    ("dead_zone".to_string(), Json::Number(axis.dead_zone as f64)),
+                    ("bindings".to_string(), Json::Array(axis.bindings.iter().map(|b| Json::String(b.to_string())).collect())),
+                ]))
+            }).collect())),
+        ]).to_string()
+    }
+
+    /// 加载绑定配置, 替换所有绑定, 出错时不修改
+    pub fn load_profile(&mut self, text: &str) -> Result<()> {
+        let root = Json::parse(text)?;
+        let mut actions = HashMap::new();
+        for (name, value) in object(&root, "actions")? {
+            let bindings = strings(value).with_context(|| format!("actions.{}", name))?;
+            actions.insert(name.clone(), bindings);
+        }
+        let mut axes = HashMap::new();
+        for (name, value) in object(&root, "axes")? {
+            let axis = parse_axis(value).with_context(|| format!("axes.{}", name))?;
+            axes.insert(name.clone(), axis);
+        }
+        self.actions = actions;
+        self.axes = axes;
+        Ok(())
+    }
+}
+
+fn object<'a>(root: &'a Json, name: &str) -> Result<&'a [(String, Json)]> {
+    match root.get(name) {
+        None => Ok(&[]),
+        Some(value) => value.as_object().ok_or_else(|| anyhow!("{}: 应为对象", name)),
+    }
+}
+
+fn strings<T: FromStr<Err = anyhow::Error>>(value: &Json) -> Result<Vec<T>> {
+    let items = value.as_array().ok_or_else(|| anyhow!("绑定应为数组"))?;
+    items.iter().map(|item| item.as_str().ok_or_else(|| anyhow!("绑定应为字符串"))?.parse()).collect()
+}
+
+fn parse_axis(value: &Json) -> Result<Axis> {
+    let bindings = strings(value.get("bindings").ok_or_else(|| anyhow!("缺少字段\"bindings\""))?)?;
+    let dead_zone = match value.get("dead_zone") {
+        None => Axis::default().dead_zone,
+        Some(value) => value.as_f64().ok_or_else(|| anyhow!("dead_zone应为数字"))? as f32,
+    };
+    if !(0. ..1.).contains(&dead_zone) {
+        bail!("dead_zone必须在0~1之间");
+    }
+    Ok(Axis { bindings, dead_zone })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyMods;
+
+    fn key_down(key: KeyCode) -> Event {
+        Event::KeyDown { key, modifiers: KeyMods::default(), repeat: false }
+    }
+
+    fn key_up(key: KeyCode) -> Event {
+        Event::KeyUp { key, modifiers: KeyMods::default() }
+    }
+
+    #[test]
+    fn test_input_map() {
+        let mut input = InputMap::new();
+        input.bind("jump", Binding::Key(KeyCode::Space));
+        input.bind("jump", Binding::Gamepad(GamepadButton::South));
+        input.bind_axis("move_x", AxisBinding::keys(KeyCode::Left, KeyCode::Right));
+        input.bind_axis("move_x", AxisBinding::stick(GamepadAxis::LeftStickX));
+
+        input.event(key_down(KeyCode::Space));
+        assert!(input.is_pressed("jump") && input.is_down("jump"));
+        input.gamepad_button(GamepadButton::South, true);
+        input.tick();
+        //忽略重复按键
+        input.event(Event::KeyDown { key: KeyCode::Space, modifiers: KeyMods::default(), repeat: true });
+        assert!(!input.is_pressed("jump"));
+        input.event(key_up(KeyCode::Space));
+        //手柄键还按着
+        assert!(!input.is_pressed("jump") && !input.is_released("jump") && input.is_down("jump"));
+        input.gamepad_button(GamepadButton::South, false);
+        assert!(input.is_released("jump"));
+        input.tick();
+        assert!(!input.is_down("jump") && !input.is_released("jump"));
+
+        input.event(key_down(KeyCode::Left));
+        assert_eq!(input.axis("move_x"), -1.);
+        input.gamepad_axis(GamepadAxis::LeftStickX, 0.1);
+        assert_eq!(input.axis("move_x"), -1.);
+        input.event(key_up(KeyCode::Left));
+        assert_eq!(input.axis("move_x"), 0.);
+        input.gamepad_axis(GamepadAxis::LeftStickX, 0.6);
+        assert!((input.axis("move_x") - 0.5).abs() < 1e-6);
+
+        //运行时修改绑定
+        input.rebind("jump", vec![Binding::Key(KeyCode::Z)]);
+        input.event(key_down(KeyCode::Z));
+        assert_eq!(input.last_pressed(), Some(Binding::Key(KeyCode::Z)));
+        assert!(input.is_pressed("jump"));
+        input.bind("fire", Binding::Mouse(MouseButton::Left));
+        input.event(Event::MouseDown(MouseButton::Left, 10., 20.));
+        assert!(input.is_pressed("fire"));
+        //停顿不代表失去焦点, 不松开按键
+        input.event(Event::Stalled { ms: 800. });
+        assert!(input.is_down("jump"));
+        input.release_all();
+        assert!(input.is_released("jump") && input.axis("move_x") == 0.);
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut input = InputMap::new();
+        input.bind("fire", Binding::Mouse(MouseButton::Left));
+        input.bind("fire", Binding::Key(KeyCode::LeftControl));
+        input.bind_axis("move_y", AxisBinding::keys(KeyCode::Up, KeyCode::Down));
+        input.bind_axis("move_y", AxisBinding::inverted(GamepadAxis::LeftStickY));
+        input.set_dead_zone("move_y", 0.25);
+        let profile = input.profile();
+        assert_eq!(profile, r#"{"actions": {"fire": ["Mouse:Left", "Key:LeftControl"]}, "axes": {"move_y": {"dead_zone": 0.25, "bindings": ["Key:Up/Key:Down", "-Axis:LeftStickY"]}}}"#);
+
+        let mut loaded = InputMap::new();
+        loaded.load_profile(&profile).unwrap();
+        assert_eq!(loaded.bindings("fire"), input.bindings("fire"));
+        assert_eq!(loaded.axis_binding("move_y"), input.axis_binding("move_y"));
+
+        let err = loaded.load_profile(r#"{"actions": {"fire": ["Key:Nope"]}}"#).unwrap_err();
+        assert_eq!(format!("{:#}", err), "actions.fire: 无效的绑定: Key:Nope");
+        assert_eq!(loaded.bindings("fire").len(), 2);
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+
+//最小的JSON解析和输出, 供资源清单、输入配置等使用, 不引入额外依赖
+
+/// JSON值
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// 保持原有顺序
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json> {
+        let mut parser = JsonParser { chars: text.chars().collect(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("多余的内容"));
+        }
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+/// 输出紧凑格式的JSON
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            //JSON不支持NaN和无穷大
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ": {}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn error(&self, msg: &str) -> anyhow::Error {
+        let line = self.chars[..self.pos.min(self.chars.len())].iter().filter(|c| **c == '\n').count() + 1;
+        anyhow!("JSON格式错误(第{}行): {}", line, msg)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("缺少'{}'", c)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(self.error("无效的值"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    if self.peek() != Some('"') {
+                        return Err(self.error("缺少字段名"));
+                    }
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.error("缺少','或'}'")),
+                    }
+                }
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("缺少','或']'")),
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("无效的值")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = *self.chars.get(self.pos).ok_or_else(|| self.error("字符串没有结束"))?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let e = *self.chars.get(self.pos).ok_or_else(|| self.error("字符串没有结束"))?;
+                    self.pos += 1;
+                    s.push(match e {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        '"' | '\\' | '/' => e,
+                        'u' => self.unicode()?,
+                        _ => return Err(self.error(&format!("无效的转义\\{}", e))),
+                    });
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("字符串中有控制字符")),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let hex: String = self.chars.get(self.pos..self.pos + 4).ok_or_else(|| self.error("无效的\\u转义"))?.iter().collect();
+        let code = u32::from_str_radix(&hex, 16).map_err(|_| self.error("无效的\\u转义"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    //\uXXXX, 辅助平面的字符用UTF-16代理对表示
+    fn unicode(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if self.chars.get(self.pos..self.pos + 2) != Some(&['\\', 'u']) {
+                    return Err(self.error("缺少低位代理"));
+                }
+                self.pos += 2;
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(self.error("无效的低位代理"));
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => return Err(self.error("无效的代理对")),
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("无效的\\u转义"))
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    //-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        if self.chars.get(self.pos) == Some(&'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        let mut valid = self.digits() > 0 && !(self.chars[int_start] == '0' && self.pos - int_start > 1);
+        if self.chars.get(self.pos) == Some(&'.') {
+            self.pos += 1;
+            valid &= self.digits() > 0;
+        }
+        if matches!(self.chars.get(self.pos), Some('e' | 'E')) {
+            self.pos += 1;
+            if matches!(self.chars.get(self.pos), Some('+' | '-')) {
+                self.pos += 1;
+            }
+            valid &= self.digits() > 0;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse::<f64>() {
+            Ok(n) if valid && n.is_finite() => Ok(Json::Number(n)),
+            _ => Err(self.error(&format!("无效的数字{}", text))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = Json::parse(r#" { "a": [1, -2.5, 3e2, 0.5E-1, -0], "b": { "c": null, "d": true, "e": false }, "f": [] } "#).unwrap();
+        assert_eq!(value.get("a").unwrap().as_array().unwrap().iter().filter_map(Json::as_f64).collect::<Vec<_>>(), vec![1., -2.5, 300., 0.05, 0.]);
+        assert_eq!(value.get("b").unwrap().get("c"), Some(&Json::Null));
+        assert_eq!(value.get("b").unwrap().get("e").and_then(Json::as_bool), Some(false));
+        assert_eq!(value.get("f").and_then(Json::as_array), Some(&[][..]));
+        assert_eq!(value.as_object().unwrap().iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(), vec!["a", "b", "f"]);
+
+        let err = |text: &str| Json::parse(text).unwrap_err().to_string();
+        for text in ["01", "1.", ".5", "-", "1e", "1e+", "+1", "1-2", "1e999", "0x10"] {
+            assert!(err(text).starts_with("JSON格式错误(第1行)"), "{}", text);
+        }
+        assert_eq!(err("1.e5"), "JSON格式错误(第1行): 无效的数字1.e5");
+        assert_eq!(err("{\n\"a\": 1,\n}"), "JSON格式错误(第3行): 缺少字段名");
+        assert_eq!(err("[1 2]"), "JSON格式错误(第1行): 缺少','或']'");
+        assert_eq!(err("[1] x"), "JSON格式错误(第1行): 多余的内容");
+        assert_eq!(err("tru"), "JSON格式错误(第1行): 无效的值");
+    }
+
+    #[test]
+    fn test_string() {
+        let parse = |text: &str| Json::parse(text).unwrap().as_str().unwrap().to_string();
+        assert_eq!(parse(r#""a\"b\\c\/d\n\t\r\b\f""#), "a\"b\\c/d\n\t\r\u{8}\u{c}");
+        assert_eq!(parse(r#""中文 é""#), "中文 é");
+        assert_eq!(parse(r#""😀 😀""#), "😀 😀");
+
+        let err = |text: &str| Json::parse(text).unwrap_err().to_string();
+        assert_eq!(err(r#""\x""#), "JSON格式错误(第1行): 无效的转义\\x");
+        assert_eq!(err(r#""\u12""#), "JSON格式错误(第1行): 无效的\\u转义");
+        assert_eq!(err(r#""\ud83d""#), "JSON格式错误(第1行): 缺少低位代理");
+        assert_eq!(err(r#""\ud83d\u0041""#), "JSON格式错误(第1行): 无效的低位代理");
+        assert_eq!(err(r#""\ude00""#), "JSON格式错误(第1行): 无效的代理对");
+        assert_eq!(err("\"a\nb\""), "JSON格式错误(第2行): 字符串中有控制字符");
+        assert_eq!(err("\"abc"), "JSON格式错误(第1行): 字符串没有结束");
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Json::Object(vec![
+            ("s".to_string(), Json::String("\"\\\n\u{1}😀".to_string())),
+            ("n".to_string(), Json::Array(vec![Json::Number(1.5), Json::Number(-3.), Json::Number(f64::NAN)])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(text, r#"{"s": "\"\\\n\u0001😀", "n": [1.5, -3, null]}"#);
+        assert_eq!(Json::parse(&text).unwrap().get("s"), value.get("s"));
+    }
+}
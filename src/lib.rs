@@ -1,14 +1,51 @@
+pub mod assets;
+pub mod audio;
+pub mod effects;
+pub mod embed;
 pub mod graphics;
 pub mod engine;
-use graphics::{Transform, draw_text};
-use macroquad::{prelude::{Texture2D, rand, Rect, screen_width, screen_height, Color, Vec2, BLACK, draw_rectangle, vec2, next_frame, KeyCode}, miniquad::date, camera::{set_camera, set_default_camera, Camera2D}, rand::srand};
+pub mod input;
+pub mod json;
+pub mod manifest;
+pub mod replay;
+pub mod scene;
+pub mod text;
+use std::{cell::{Cell, RefCell}, collections::HashMap};
+use effects::EffectChain;
+use scene::SceneChange;
+use engine::GameEngine;
+use replay::{Player, Recording};
+use graphics::{Transform, NineSlice, Shape, TextRun};
+use text::TextStyle;
+use anyhow::{bail, Context, Result};
+use macroquad::{prelude::{Texture2D, RenderTarget, Image, render_target, clear_background, draw_texture_ex, DrawTextureParams, FilterMode, WHITE, rand, Rect, screen_width, screen_height, Color, Vec2, BLACK, vec2, next_frame, show_mouse, prevent_quit, is_quit_requested, request_new_screen_size, KeyCode, MouseButton, is_simulating_mouse_with_touch}, miniquad::{self, conf::Icon, date, TouchPhase}, input::utils::{register_input_subscriber, repeat_all_miniquad_input}, window::Conf, logging::warn, camera::{set_camera, set_default_camera, Camera2D}, rand::srand};
+pub use macroquad::miniquad::KeyMods;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// 输入事件, 坐标都已转换为游戏坐标
 pub enum Event {
     MouseMove(f32, f32),
-    Click(f32, f32),
-    KeyDown(KeyCode),
-    KeyUp(KeyCode),
+    /// 鼠标按下(按键, x, y)
+    MouseDown(MouseButton, f32, f32),
+    /// 鼠标松开(按键, x, y)
+    MouseUp(MouseButton, f32, f32),
+    /// 鼠标滚轮(横向, 纵向)
+    MouseWheel(f32, f32),
+    /// 触摸开始(触点id, x, y)
+    TouchStart(u64, f32, f32),
+    TouchMove(u64, f32, f32),
+    /// 触摸结束或被取消
+    TouchEnd(u64, f32, f32),
+    /// 输入的字符, 用于输入名字等文本, 不包含控制字符
+    Text(char),
+    /// repeat: 按住不放时系统重复发送
+    KeyDown { key: KeyCode, modifiers: KeyMods, repeat: bool },
+    KeyUp { key: KeyCode, modifiers: KeyMods },
+    /// 窗口大小改变(宽, 高)
+    Resize(f32, f32),
+    /// 两帧间隔超过STALL_TIME(500ms), ms为实际间隔. 可能是窗口被拖动、最小化或程序卡顿,
+    /// macroquad没有窗口焦点事件, 这不代表失去焦点. 停顿的时间不会补上更新
+    Stalled { ms: f32 },
 }
 
 //计时器
@@ -47,28 +84,54 @@ impl AnimationTimer {
 }
 
 /// 绘制对象, 可为空不进行绘制
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Drawable{
     Texture2D(Texture2D),
+    /// 九宫格面板
+    NineSlice(NineSlice),
+    /// 纯色矩形
+    Rectangle{ width: f32, height: f32, color: Color },
+    /// 矢量图形
+    Shape(Shape),
+    /// 文字
+    Text(TextRun),
+    /// 渲染目标的纹理, 绘制时会上下翻转
+    RenderTarget(Texture2D),
+    /// 只有大小, 不绘制, 用于无窗口运行
+    Blank{ width: f32, height: f32 },
     None,
 }
 
 impl Drawable{
     pub fn width(&self) -> f32{
         match self{
-            &Self::None => 0.,
-            &Self::Texture2D(t) => t.width()
+            Self::None => 0.,
+            Self::Texture2D(t) | Self::RenderTarget(t) => t.width(),
+            Self::NineSlice(panel) => panel.size.x,
+            Self::Rectangle{ width, .. } | Self::Blank{ width, .. } => *width,
+            Self::Shape(shape) => shape.width(),
+            Self::Text(run) => run.measure().x,
         }
     }
 
     pub fn height(&self) -> f32{
         match self{
-            &Self::None => 0.,
-            &Self::Texture2D(t) => t.height()
+            Self::None => 0.,
+            Self::Texture2D(t) | Self::RenderTarget(t) => t.height(),
+            Self::NineSlice(panel) => panel.size.y,
+            Self::Rectangle{ height, .. } | Self::Blank{ height, .. } => *height,
+            Self::Shape(shape) => shape.height(),
+            Self::Text(run) => run.measure().y,
         }
     }
 }
 
+impl From<RenderTarget> for Drawable{
+    fn from(target: RenderTarget) -> Self {
+        Drawable::RenderTarget(target.texture)
+    }
+}
+
 pub struct SubImage {
     image: Drawable,
     region: Rect,
@@ -79,8 +142,16 @@ impl SubImage {
         SubImage { image, region }
     }
 
+    pub fn image(&self) -> &Drawable {
+        &self.image
+    }
+
+    pub fn region(&self) -> Rect {
+        self.region
+    }
+
     pub fn draw(&self, transform: Option<Transform>, dest: Rect) {
-        graphics::draw_image(transform, self.image, Some(self.region), Some(dest));
+        graphics::draw_image(transform, &self.image, Some(self.region), Some(dest));
     }
 }
 
@@ -110,6 +181,29 @@ impl Animation {
         }
     }
 
+    pub fn image(&self) -> &Drawable {
+        &self.image
+    }
+
+    pub fn set_image(&mut self, image: Drawable) {
+        self.image = image;
+    }
+
+    pub fn frames(&self) -> &[Rect] {
+        &self.frames
+    }
+
+    /// 替换帧, 保留当前帧和播放状态
+    pub fn set_frames(&mut self, frames: Vec<Rect>) {
+        let (end, len) = (self.is_end(), frames.len() as i32);
+        self.frames = frames;
+        if end {
+            self.current = len;
+        } else if self.current >= len {
+            self.current = len - 1;
+        }
+    }
+
     pub fn active(image: Drawable, frames: Vec<Rect>, fps: f64) -> Animation {
         let mut anim = Self::new(image, frames, fps);
         anim.start();
@@ -206,7 +300,7 @@ impl Animation {
         // println!("anim draw current={}", current);
         graphics::draw_image(
             transform,
-            self.image,
+            &self.image,
             Some(self.frames[current as usize]),
             Some(dest),
         );
@@ -390,8 +484,44 @@ impl<T: Default> Size<T> {
     }
 }
 
+/// 画面缩放方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// 不缩放
+    #[default]
+    None,
+    /// 按比例缩放到窗口内, 空白部分填充背景色
+    Fit,
+    /// 按整数倍缩放到窗口内, 先绘制到原始大小的纹理再按最近邻放大, 适合像素风格
+    PixelPerfect,
+    /// 拉伸到整个窗口
+    Stretch,
+    /// 按比例缩放填满窗口, 超出部分被裁剪
+    Fill,
+}
+
+impl ScaleMode {
+    /// 计算画面在窗口中的位置和大小, center为false时画面在左上角
+    pub fn viewport(&self, width: f32, height: f32, window_width: f32, window_height: f32, center: bool) -> Rect {
+        let (scale_x, scale_y) = (window_width / width, window_height / height);
+        let scale = match self {
+            ScaleMode::None => 1.,
+            ScaleMode::Fit => scale_x.min(scale_y),
+            ScaleMode::PixelPerfect => scale_x.min(scale_y).floor().max(1.),
+            ScaleMode::Stretch => return Rect::new(0., 0., window_width, window_height),
+            ScaleMode::Fill => scale_x.max(scale_y),
+        };
+        let (w, h) = (width * scale, height * scale);
+        if center {
+            Rect::new((window_width - w) / 2., (window_height - h) / 2., w, h)
+        } else {
+            Rect::new(0., 0., w, h)
+        }
+    }
+}
+
 ///A builder that constructs a Window
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Settings {
     /// If the cursor should be visible over the application
     pub show_cursor: bool,
@@ -406,16 +536,21 @@ pub struct Settings {
     pub fullscreen: bool,
     /// How many times is the update method called per second
     pub ups: u64,
-    pub icon_path: Option<&'static str>, // TODO: statiC?
+    /// 窗口图标(PNG), 缩放为16x16、32x32和64x64, 网页版无效
+    pub icon_path: Option<&'static str>,
     /// 背景色[r,g,b,a]
     pub background_color: Option<Color>,
     pub window_size: Option<Vec2>,
     /// 居中绘图
     pub draw_center: bool,
-    /// 自动缩放
-    pub auto_scale: bool,
+    /// 画面缩放方式
+    pub scale_mode: ScaleMode,
     /// 显示更新频率 UPS/FPS
     pub show_ups_fps: bool,
+    /// 录制输入, run()结束时保存到这个文件, 网页版无效
+    pub record: Option<String>,
+    /// 回放录制的输入
+    pub replay: Option<String>,
 }
 
 impl Default for Settings {
@@ -429,9 +564,139 @@ impl Default for Settings {
             icon_path: None,
             background_color: None,
             draw_center: true,
-            auto_scale: false,
+            scale_mode: ScaleMode::None,
             window_size: None,
             show_ups_fps: false,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+impl Settings {
+    /// 检查设置是否有效
+    pub fn validate(&self) -> Result<()> {
+        if self.ups == 0 {
+            bail!("ups必须大于0");
+        }
+        for (name, size) in [("min_size", self.min_size), ("max_size", self.max_size)] {
+            if let Some(size) = size {
+                if size.width <= 0. || size.height <= 0. {
+                    bail!("{}必须大于0: {}x{}", name, size.width, size.height);
+                }
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min.width > max.width || min.height > max.height {
+                bail!("min_size({}x{})大于max_size({}x{})", min.width, min.height, max.width, max.height);
+            }
+        }
+        if let Some(size) = self.window_size {
+            if size.x <= 0. || size.y <= 0. {
+                bail!("window_size必须大于0: {}x{}", size.x, size.y);
+            }
+            if size != clamp_size(size, self.min_size, self.max_size) {
+                bail!("window_size({}x{})超出min_size和max_size的范围", size.x, size.y);
+            }
+        }
+        if let Some(path) = self.icon_path {
+            load_icon(path)?;
+        }
+        if self.record.is_some() && self.replay.is_some() {
+            bail!("record和replay不能同时使用");
+        }
+        //wasm不能写文件
+        #[cfg(target_arch = "wasm32")]
+        if self.record.is_some() {
+            bail!("wasm不支持record");
+        }
+        Ok(())
+    }
+
+    /// 生成macroquad窗口设置, 用于#[macroquad::main(conf)]
+    pub fn window_conf(&self, title: &str) -> Conf {
+        let mut conf = Conf {
+            window_title: title.to_string(),
+            fullscreen: self.fullscreen,
+            ..Default::default()
+        };
+        if let Some(size) = self.window_size {
+            conf.window_width = size.x as i32;
+            conf.window_height = size.y as i32;
+        }
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            conf.window_resizable = min.width != max.width || min.height != max.height;
+        }
+        if let Some(path) = self.icon_path {
+            match load_icon(path) {
+                Ok(icon) => conf.icon = Some(icon),
+                Err(err) => warn!("{:#}", err),
+            }
+        }
+        conf
+    }
+
+    /// 应用运行时可以修改的设置: 光标和全屏
+    pub fn apply(&self) {
+        show_mouse(self.show_cursor);
+        set_fullscreen(self.fullscreen);
+    }
+}
+
+thread_local! {
+    static FULLSCREEN: Cell<bool> = const { Cell::new(false) };
+    /// 解码过的窗口图标, window_conf和validate共用
+    static ICONS: RefCell<HashMap<String, Icon>> = RefCell::new(HashMap::new());
+}
+
+/// 切换全屏, 代替macroquad的set_fullscreen, run()按当前状态限制窗口大小
+pub fn set_fullscreen(fullscreen: bool) {
+    FULLSCREEN.with(|f| f.set(fullscreen));
+    macroquad::window::set_fullscreen(fullscreen);
+}
+
+pub fn is_fullscreen() -> bool {
+    FULLSCREEN.with(Cell::get)
+}
+
+/// 把尺寸限制在最小和最大尺寸之间
+fn clamp_size(size: Vec2, min_size: Option<Size<f32>>, max_size: Option<Size<f32>>) -> Vec2 {
+    let mut size = size;
+    if let Some(min) = min_size {
+        size = size.max(vec2(min.width, min.height));
+    }
+    if let Some(max) = max_size {
+        size = size.min(vec2(max.width, max.height));
+    }
+    size
+}
+
+/// 加载窗口图标, 优先使用内嵌文件, 同一个文件只解码一次
+pub fn load_icon(path: &str) -> Result<Icon> {
+    if let Some(icon) = ICONS.with(|icons| icons.borrow().get(path).cloned()) {
+        return Ok(icon);
+    }
+    let data = embed::read_file(path).context("图标加载失败")?;
+    let image = embed::decode_image(&data, path).context("图标加载失败")?;
+    let mut icon = Icon { small: [0; 16 * 16 * 4], medium: [0; 32 * 32 * 4], big: [0; 64 * 64 * 4] };
+    resize_icon(&image, &mut icon.small, 16);
+    resize_icon(&image, &mut icon.medium, 32);
+    resize_icon(&image, &mut icon.big, 64);
+    ICONS.with(|icons| icons.borrow_mut().insert(path.to_string(), icon.clone()));
+    Ok(icon)
+}
+
+/// 按最近邻缩放图标
+fn resize_icon(image: &Image, out: &mut [u8], size: usize) {
+    let (width, height) = (image.width as usize, image.height as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    for y in 0..size {
+        for x in 0..size {
+            let src = ((y * height / size) * width + x * width / size) * 4;
+            let dst = (y * size + x) * 4;
+            out[dst..dst + 4].copy_from_slice(&image.bytes[src..src + 4]);
         }
     }
 }
@@ -460,6 +725,11 @@ impl AudioType {
             AudioType::Other
         }
     }
+
+    /// macroquad是否支持播放该格式
+    pub fn is_supported(&self) -> bool {
+        matches!(self, AudioType::WAV | AudioType::OGG)
+    }
 }
 
 // pub struct AssetsFile {
@@ -536,18 +806,160 @@ pub fn inflate(rect: &Rect, dx: f32, dy: f32) -> Rect {
     Rect::new(rect.x - dx, rect.y - dy, rect.w + dx + dx, rect.h + dy + dy)
 }
 
-/// 系统时间戳ms
+thread_local! {
+    static GAME_TIME: Cell<Option<f64>> = const { Cell::new(None) };
+}
+
+/// 时间戳ms, 设置了游戏时间时返回游戏时间
 pub fn current_timestamp() -> f64{
-    date::now() * 1000.
+    GAME_TIME.with(Cell::get).unwrap_or_else(|| date::now() * 1000.)
 }
 
-pub async fn run<T: State>(state: &mut T, width: f32, height: f32, settings: Settings) {
+/// 固定current_timestamp返回的时间(ms), 录制和回放时按更新次数计算, 使动画可以重现. None恢复使用系统时间
+pub fn set_game_time(time: Option<f64>) {
+    GAME_TIME.with(|t| t.set(time));
+}
 
-    //初始化随机数种子
-    srand((date::now()*10000000.) as u64);
+/// 收集miniquad的输入事件, 鼠标和触摸坐标转换为游戏坐标
+struct EventQueue {
+    events: Vec<Event>,
+    viewport: Rect,
+    width: f32,
+    height: f32,
+    simulate_mouse: bool,
+}
+
+impl EventQueue {
+    fn logical(&self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.viewport.x) * self.width / self.viewport.w, (y - self.viewport.y) * self.height / self.viewport.h)
+    }
+}
+
+impl miniquad::EventHandler for EventQueue {
+    fn update(&mut self, _ctx: &mut miniquad::Context) {}
+    fn draw(&mut self, _ctx: &mut miniquad::Context) {}
+
+    fn mouse_motion_event(&mut self, _ctx: &mut miniquad::Context, x: f32, y: f32) {
+        let (x, y) = self.logical(x, y);
+        self.events.push(Event::MouseMove(x, y));
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut miniquad::Context, x: f32, y: f32) {
+        self.events.push(Event::MouseWheel(x, y));
+    }
+
+    fn mouse_button_down_event(&mut self, _ctx: &mut miniquad::Context, button: MouseButton, x: f32, y: f32) {
+        let (x, y) = self.logical(x, y);
+        self.events.push(Event::MouseDown(button, x, y));
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut miniquad::Context, button: MouseButton, x: f32, y: f32) {
+        let (x, y) = self.logical(x, y);
+        self.events.push(Event::MouseUp(button, x, y));
+    }
+
+    fn touch_event(&mut self, _ctx: &mut miniquad::Context, phase: TouchPhase, id: u64, x: f32, y: f32) {
+        let (x, y) = self.logical(x, y);
+        self.events.push(match phase {
+            TouchPhase::Started => Event::TouchStart(id, x, y),
+            TouchPhase::Moved => Event::TouchMove(id, x, y),
+            TouchPhase::Ended | TouchPhase::Cancelled => Event::TouchEnd(id, x, y),
+        });
+        //和macroquad一样, 默认用触摸模拟鼠标左键
+        if self.simulate_mouse {
+            self.events.push(match phase {
+                TouchPhase::Started => Event::MouseDown(MouseButton::Left, x, y),
+                TouchPhase::Moved => Event::MouseMove(x, y),
+                TouchPhase::Ended | TouchPhase::Cancelled => Event::MouseUp(MouseButton::Left, x, y),
+            });
+        }
+    }
+
+    fn char_event(&mut self, _ctx: &mut miniquad::Context, character: char, _keymods: KeyMods, _repeat: bool) {
+        if !character.is_control() {
+            self.events.push(Event::Text(character));
+        }
+    }
+
+    fn key_down_event(&mut self, _ctx: &mut miniquad::Context, key: KeyCode, modifiers: KeyMods, repeat: bool) {
+        self.events.push(Event::KeyDown { key, modifiers, repeat });
+    }
+
+    fn key_up_event(&mut self, _ctx: &mut miniquad::Context, key: KeyCode, modifiers: KeyMods) {
+        self.events.push(Event::KeyUp { key, modifiers });
+    }
+}
+
+/// 两帧间隔超过这个时间(ms)时发送Event::Stalled
+pub const STALL_TIME: f64 = 500.;
+
+/// 运行游戏直到State返回Flow::Quit、用户关闭窗口或出错, 返回第一个使游戏停止的错误
+pub async fn run<T: State>(state: &mut T, width: f32, height: f32, settings: Settings) -> Result<()> {
+
+    settings.validate().context("Settings错误")?;
+    settings.apply();
+
+    let mut player = match &settings.replay {
+        Some(path) => {
+            let recording = Recording::load(path).await?;
+            if recording.ups != settings.ups {
+                bail!("录制时ups为{}, 回放需要相同的ups", recording.ups);
+            }
+            Some(Player::new(recording))
+        }
+        None => None,
+    };
+    //初始化随机数种子, 回放时使用录制的种子
+    let seed = match &player {
+        Some(player) => player.recording().seed,
+        None => (date::now()*10000000.) as u64,
+    };
+    srand(seed);
+    let mut recording = settings.record.as_ref().map(|_| Recording::new(seed, settings.ups));
+
+    //初始化游戏引擎
+    if !state.initialize() {
+        bail!("游戏引擎初始化失败");
+    }
+    //关闭窗口时先询问State::quit_requested
+    prevent_quit();
+    let mut result = run_loop(state, width, height, &settings, &mut player, &mut recording).await;
+
+    //出错时也保存录制, 用于重现问题
+    if let (Some(recording), Some(path)) = (recording.as_mut(), settings.record.as_deref()) {
+        if let Some(engine) = state.engine() {
+            recording.sprites = replay::sprite_state(engine);
+        }
+        result = result.and(recording.save(path));
+    }
+
+    state.end();
+    result
+}
+
+async fn run_loop<T: State>(state: &mut T, width: f32, height: f32, settings: &Settings, player: &mut Option<Player>, recording: &mut Option<Recording>) -> Result<()> {
+    let mut window_size = (screen_width(), screen_height());
+    let mut last_frame = current_timestamp();
+    let input_subscriber = register_input_subscriber();
+    let mut events = EventQueue { events: vec![], viewport: Rect::new(0., 0., width, height), width, height, simulate_mouse: true };
+    //录制和回放时的游戏开始时间
+    let game_start = (player.is_some() || recording.is_some()).then(current_timestamp);
+    //总更新次数
+    let mut ticks: u64 = 0;
 
     let background_color = settings.background_color.unwrap_or(BLACK);
-    let (auto_scale, draw_center) = (settings.auto_scale, settings.draw_center);
+    //画面先绘制到原始大小的纹理, 再缩放到窗口中
+    let target = render_target(width as u32, height as u32);
+    target.texture.set_filter(match settings.scale_mode {
+        ScaleMode::PixelPerfect | ScaleMode::None => FilterMode::Nearest,
+        _ => FilterMode::Linear,
+    });
+    let camera = Camera2D {
+        zoom: vec2(2. / width, 2. / height),
+        target: vec2(width / 2., height / 2.),
+        render_target: Some(target),
+        ..Default::default()
+    };
 
     // 更新频率
     let mut update_timer = AnimationTimer::new(settings.ups as f64);
@@ -558,11 +970,70 @@ pub async fn run<T: State>(state: &mut T, width: f32, height: f32, settings: Set
     let mut ups = 0;
     let mut fps = 0;
     loop {
+        //两帧间隔过长时重置更新计时器, 避免之后连续更新
+        let now = current_timestamp();
+        if now - last_frame > STALL_TIME {
+            events.events.push(Event::Stalled { ms: (now - last_frame) as f32 });
+            update_timer.reset();
+            update_fps_timer.reset();
+        }
+        last_frame = now;
+
+        if (screen_width(), screen_height()) != window_size {
+            window_size = (screen_width(), screen_height());
+            events.events.push(Event::Resize(window_size.0, window_size.1));
+        }
+
+        if is_quit_requested() && state.quit_requested() {
+            break;
+        }
+
+        //在帧开始时替换热重载的资源
+        #[cfg(not(target_arch = "wasm32"))]
+        state.hot_reload();
+
         //按帧率更新
-        if update_timer.ready_for_next_frame(){
-            state.update();
+        let ready = update_timer.ready_for_next_frame();
+
+        //录制和回放时事件和更新使用游戏时间
+        if let Some(start) = game_start {
+            set_game_time(Some(start + ticks as f64 * 1000. / settings.ups as f64));
+        }
+
+        //分发输入事件, 回放时忽略实际的输入
+        events.viewport = settings.scale_mode.viewport(width, height, window_size.0, window_size.1, settings.draw_center);
+        events.simulate_mouse = is_simulating_mouse_with_touch();
+        repeat_all_miniquad_input(&mut events, input_subscriber);
+        let frame_events = match player.as_mut() {
+            Some(player) if !player.is_done(ticks) => {
+                events.events.clear();
+                player.events(ticks)
+            }
+            _ => std::mem::take(&mut events.events),
+        };
+        for event in frame_events {
+            if let Some(recording) = recording.as_mut() {
+                recording.record(ticks, event);
+            }
+            state.event(event);
+        }
+
+        if ready {
+            let flow = state.update();
+            set_game_time(None);
+            let flow = flow?;
             update_count += 1;
+            ticks += 1;
+            if let Some(recording) = recording.as_mut() {
+                recording.ticks = ticks;
+            }
+            match flow {
+                Flow::Continue => (),
+                Flow::Quit => break,
+                Flow::Scene(_) => bail!("场景切换需要使用SceneStack"),
+            }
         }
+        set_game_time(None);
         frame_count += 1;
 
         if update_fps_timer.ready_for_next_frame(){
@@ -574,85 +1045,106 @@ pub async fn run<T: State>(state: &mut T, width: f32, height: f32, settings: Set
         
         let (window_width, window_height) = (screen_width(), screen_height());
 
-        // draw_rectangle(0., 0., window_width, window_height, background_color);
-        
-        let (mut new_width, mut new_height) = (width, height);
-        let (mut scale_x, mut scale_y) = (1.0, 1.0);
-        if auto_scale {
-            //画面不超过窗口高度
-            new_height = window_height;
-            new_width = new_height / height * width;
-
-            if new_width > window_width {
-                new_width = window_width;
-                new_height = new_width / width * height;
+        //限制窗口大小
+        #[cfg(not(target_arch = "wasm32"))]
+        if !is_fullscreen() {
+            let size = clamp_size(vec2(window_width, window_height), settings.min_size, settings.max_size);
+            if size != vec2(window_width, window_height) {
+                request_new_screen_size(size.x, size.y);
             }
-            scale_x = new_width / width;
-            scale_y = new_height / height;
         }
-        let (mut trans_x, mut trans_y) = (0.0, 0.0);
-        if draw_center {
-            trans_x = (window_width - new_width) / 2.;
-            trans_y = (window_height - new_height) / 2.;
-        }
-
-        // println!("{}x{} scale={}x{}", window_width, window_height, scale_x, scale_y);
 
-        // println!("{}x{}", trans_x, trans_y);
-        let camera_zoom_x = 1./window_width*2.;
-        let camera_zoom_y = -1./window_height*2.;
-        set_camera(&Camera2D {
-            zoom: vec2( camera_zoom_x * scale_x,  camera_zoom_y * scale_y),
-            offset: vec2(-1. + camera_zoom_x*trans_x, 1. + camera_zoom_y*trans_y),
-            ..Default::default()
-        });
-        
-        state.draw();
+        let viewport = settings.scale_mode.viewport(width, height, window_width, window_height, settings.draw_center);
 
-        // draw_rectangle(0., 0., width/2., height/2., GRAY);
+        set_camera(&camera);
+        clear_background(background_color);
+        state.draw()?;
 
         //显示UPS/FPS
         if settings.show_ups_fps {
-            let _ = draw_text(
-                &format!("FPS:{} UPS:{}", fps, ups),
-                20.,
-                height - 30.,
-                &[255, 255, 0, 200],
-                10.,
-            );
+            text::draw_text(&format!("FPS:{} UPS:{}", fps, ups), 20., height - 40., &TextStyle {
+                size: 10,
+                color: Color::from_rgba(255, 255, 0, 200),
+                ..Default::default()
+            });
+        }
+
+        //后期处理
+        let texture = match state.effects() {
+            Some(effects) => {
+                effects.tick();
+                effects.apply(target.texture, width, height)
+            }
+            None => target.texture,
+        };
+
+        if state.wants_screenshot() {
+            state.screenshot(texture.get_texture_data());
         }
 
         set_default_camera();
-        
-        //遮盖上部分窗口
-        draw_rectangle(0., 0., window_width, trans_y, background_color);
-        //遮盖下部分窗口
-        draw_rectangle(
-            0.,
-            trans_y + height * scale_y,
-            window_width,
-            window_height - (trans_y + height * scale_y),
-            background_color
-        );
-        //遮盖左部分窗口
-        draw_rectangle(0.0, 0.0, trans_x, window_height, background_color);
-        //遮盖右部分窗口
-        draw_rectangle(
-            trans_x + width * scale_x,
-            0.0,
-            window_width - (trans_x + width * scale_x),
-            window_height,
-            background_color
-        );
-        
+        clear_background(background_color);
+        draw_texture_ex(texture, viewport.x, viewport.y, WHITE, DrawTextureParams {
+            dest_size: Some(vec2(viewport.w, viewport.h)),
+            ..Default::default()
+        });
+
         next_frame().await
     }
+    Ok(())
+}
+
+/// State::update的返回值
+pub enum Flow {
+    Continue,
+    /// 退出run()
+    Quit,
+    /// 切换场景, 由SceneStack处理
+    Scene(SceneChange),
 }
 
 pub trait State{
     fn event(&mut self, _event: Event);
-    fn update(&mut self);
-    fn draw(&mut self);
+    fn update(&mut self) -> Result<Flow>;
+    fn draw(&mut self) -> Result<()>;
+    /// 每帧开始时调用, 用于替换热重载的资源, 只用于桌面平台
+    fn hot_reload(&mut self) {}
+    /// 返回true时在绘制后截图, 截图通过screenshot传回
+    fn wants_screenshot(&mut self) -> bool {
+        false
+    }
+    /// 原始大小的游戏画面截图
+    fn screenshot(&mut self, _image: Image) {}
+    /// 绘制后按顺序应用的全屏效果
+    fn effects(&mut self) -> Option<&mut EffectChain> {
+        None
+    }
+    /// 场景进入场景栈
+    fn enter(&mut self) {}
+    /// 场景离开场景栈
+    fn exit(&mut self) {}
+    /// 其他场景压入到上面
+    fn pause(&mut self) {}
+    /// 上面的场景弹出
+    fn resume(&mut self) {}
+    /// 返回游戏引擎
+    fn engine(&mut self) -> Option<&mut dyn GameEngine> {
+        None
+    }
+    /// run()开始时调用, 默认调用engine()的GameEngine::initialize, 返回false时run()出错
+    fn initialize(&mut self) -> bool {
+        self.engine().map(|engine| engine.initialize()).unwrap_or(true)
+    }
+    /// run()结束时调用, 默认调用engine()的GameEngine::end
+    fn end(&mut self) {
+        if let Some(engine) = self.engine() {
+            engine.end();
+        }
+    }
+    /// 用户关闭窗口时调用, 返回false取消退出
+    fn quit_requested(&mut self) -> bool {
+        true
+    }
 }
 
 #[test]
@@ -661,4 +1153,36 @@ fn test(){
     for _ in 0..10{
         println!("{}", rand_uuid())
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_settings(){
+    assert!(Settings::default().validate().is_ok());
+    let settings = Settings{
+        min_size: Some(Size::new(100., 100.)),
+        max_size: Some(Size::new(400., 300.)),
+        window_size: Some(vec2(500., 200.)),
+        ..Default::default()
+    };
+    assert_eq!(settings.validate().unwrap_err().to_string(), "window_size(500x200)超出min_size和max_size的范围");
+    assert_eq!(clamp_size(vec2(50., 500.), settings.min_size, settings.max_size), vec2(100., 300.));
+    assert!(Settings{ ups: 0, ..Default::default() }.validate().is_err());
+    assert!(Settings{ icon_path: Some("not_found.png"), ..Default::default() }.validate().is_err());
+}
+
+#[test]
+fn test_scale_mode(){
+    assert_eq!(ScaleMode::None.viewport(100., 50., 400., 300., true), Rect::new(150., 125., 100., 50.));
+    assert_eq!(ScaleMode::Fit.viewport(100., 50., 250., 300., true), Rect::new(0., 87.5, 250., 125.));
+    assert_eq!(ScaleMode::PixelPerfect.viewport(100., 50., 250., 300., true), Rect::new(25., 100., 200., 100.));
+    assert_eq!(ScaleMode::PixelPerfect.viewport(100., 50., 50., 50., false), Rect::new(0., 0., 100., 50.));
+    assert_eq!(ScaleMode::Stretch.viewport(100., 50., 250., 300., true), Rect::new(0., 0., 250., 300.));
+    assert_eq!(ScaleMode::Fill.viewport(100., 50., 250., 300., true), Rect::new(-175., 0., 600., 300.));
+}
+
+#[test]
+fn test_event_queue(){
+    let events = EventQueue{ events: vec![], viewport: ScaleMode::Fit.viewport(100., 50., 250., 300., true), width: 100., height: 50., simulate_mouse: true };
+    assert_eq!(events.logical(0., 87.5), (0., 0.));
+    assert_eq!(events.logical(125., 150.), (50., 25.));
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::{logging::warn, prelude::{vec2, Rect, Vec2}};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::assets::FileWatcher;
+
+use crate::{embed, json::Json, assets::{AssetManager, FontHandle, LoadingScreen, Reload, SoundAssetHandle, TextureHandle}, audio::{AudioGroup, AudioManager, SoundHandle, SoundOptions}, engine::{BackgroundLayer, LayerScale, Repeat}, text::TextFont, Animation, AudioType, Drawable};
+
+//资源清单: 用JSON描述游戏的图片、精灵动画、声音、字体和背景图层
+//
+// {
+//     "textures": { "timmy": "static/Timmy.png" },
+//     "animations": {
+//         "timmy": { "texture": "timmy", "fps": 25, "repeat": true, "frames": [[0, 0, 33, 17], [0, 17, 33, 17]] },
+//         "explosion": { "texture": "explosion", "fps": 25, "grid": { "width": 17, "height": 17, "columns": 1, "count": 8 } }
+//     },
+//     "sounds": { "missile": { "path": "static/TMissile.ogg", "group": "sfx", "max_voices": 4, "length": 0.5 } },
+//     "fonts": { "hint": "static/font.ttf" },
+//     "backgrounds": { "clouds": { "texture": "clouds", "viewport": [0, 0, 600, 450], "velocity": [1, 0], "parallax": [0.25, 0.25] } }
+// }
+
+/// 精灵动画
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationDesc {
+    pub texture: String,
+    pub frames: Vec<Rect>,
+    pub fps: f64,
+    pub repeat: bool,
+}
+
+/// 背景图层
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundDesc {
+    pub texture: String,
+    pub viewport: Rect,
+    pub velocity: Vec2,
+    pub parallax: Vec2,
+    pub dest: Option<Rect>,
+    pub repeat: Repeat,
+    pub scale: LayerScale,
+}
+
+/// 资源清单, 各项按key保存
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub textures: HashMap<String, String>,
+    pub animations: HashMap<String, AnimationDesc>,
+    pub sounds: HashMap<String, (String, SoundOptions)>,
+    pub fonts: HashMap<String, String>,
+    pub backgrounds: HashMap<String, BackgroundDesc>,
+}
+
+/// 取对象的字段, name用于错误信息
+fn section<'a>(root: &'a Json, name: &str) -> Result<&'a [(String, Json)]> {
+    match root.get(name) {
+        None => Ok(&[]),
+        Some(value) => value.as_object().ok_or_else(|| anyhow!("{}: 应为对象", name)),
+    }
+}
+
+fn number(value: &Json, field: &str) -> Result<f64> {
+    value.get(field).and_then(Json::as_f64).ok_or_else(|| anyhow!("缺少数字字段\"{}\"", field))
+}
+
+fn numbers<const N: usize>(value: &Json) -> Option<[f32; N]> {
+    let items = value.as_array()?;
+    if items.len() != N {
+        return None;
+    }
+    let mut out = [0.; N];
+    for (o, item) in out.iter_mut().zip(items) {
+        *o = item.as_f64()? as f32;
+    }
+    Some(out)
+}
+
+fn rect(value: &Json) -> Result<Rect> {
+    numbers::<4>(value).map(|[x, y, w, h]| Rect::new(x, y, w, h)).ok_or_else(|| anyhow!("矩形应为[x, y, width, height]"))
+}
+
+fn vector(value: Option<&Json>, default: Vec2) -> Result<Vec2> {
+    match value {
+        None => Ok(default),
+        Some(value) => numbers::<2>(value).map(|[x, y]| vec2(x, y)).ok_or_else(|| anyhow!("向量应为[x, y]")),
+    }
+}
+
+/// 路径字段, 可以是字符串或带path字段的对象
+fn path(value: &Json) -> Result<String> {
+    let path = value.as_str().or_else(|| value.get("path").and_then(Json::as_str)).ok_or_else(|| anyhow!("缺少路径"))?;
+    if path.is_empty() {
+        bail!("路径为空");
+    }
+    Ok(path.to_string())
+}
+
+fn parse_animation(value: &Json) -> Result<AnimationDesc> {
+    let texture = value.get("texture").and_then(Json::as_str).ok_or_else(|| anyhow!("缺少字段\"texture\""))?.to_string();
+    let fps = number(value, "fps")?;
+    if fps <= 0. {
+        bail!("fps必须大于0");
+    }
+    let frames = match (value.get("frames"), value.get("grid")) {
+        (Some(frames), _) => frames.as_array().ok_or_else(|| anyhow!("frames应为数组"))?.iter().map(rect).collect::<Result<Vec<Rect>>>()?,
+        //按网格切分帧
+        (None, Some(grid)) => {
+            let (x, y) = (grid.get("x").and_then(Json::as_f64).unwrap_or(0.), grid.get("y").and_then(Json::as_f64).unwrap_or(0.));
+            let (width, height) = (number(grid, "width")?, number(grid, "height")?);
+            let columns = grid.get("columns").and_then(Json::as_f64).unwrap_or(1.).max(1.) as usize;
+            let count = number(grid, "count")? as usize;
+            (0..count).map(|i| Rect::new(
+                (x + (i % columns) as f64 * width) as f32,
+                (y + (i / columns) as f64 * height) as f32,
+                width as f32,
+                height as f32,
+            )).collect()
+        }
+        (None, None) => bail!("缺少字段\"frames\"或\"grid\""),
+    };
+    if frames.is_empty() {
+        bail!("没有帧");
+    }
+    if let Some(frame) = frames.iter().find(|f| f.w <= 0. || f.h <= 0.) {
+        bail!("帧{:?}的大小无效", frame);
+    }
+    let repeat = value.get("repeat").and_then(Json::as_bool).unwrap_or(false);
+    Ok(AnimationDesc { texture, frames, fps, repeat })
+}
+
+fn parse_sound(value: &Json) -> Result<(String, SoundOptions)> {
+    let path = path(value)?;
+    let audio_type = AudioType::test(&path);
+    if !audio_type.is_supported() {
+        bail!("不支持的音频格式{:?}", audio_type);
+    }
+    let mut options = SoundOptions::default();
+    match value.get("group").and_then(Json::as_str) {
+        None | Some("sfx") => (),
+        Some("music") => options.group = AudioGroup::Music,
+        Some(other) => bail!("未知的分组\"{}\"", other),
+    }
+    if let Some(volume) = value.get("volume").and_then(Json::as_f64) {
+        options.volume = volume as f32;
+    }
+    if let Some(max_voices) = value.get("max_voices").and_then(Json::as_f64) {
+        options.max_voices = max_voices as usize;
+    }
+    if let Some(length) = value.get("length").and_then(Json::as_f64) {
+        options.length = length as f32;
+    }
+    Ok((path, options))
+}
+
+fn parse_background(value: &Json) -> Result<BackgroundDesc> {
+    let texture = value.get("texture").and_then(Json::as_str).ok_or_else(|| anyhow!("缺少字段\"texture\""))?.to_string();
+    let viewport = rect(value.get("viewport").ok_or_else(|| anyhow!("缺少字段\"viewport\""))?)?;
+    let dest = value.get("dest").map(rect).transpose()?;
+    let repeat = match value.get("repeat").and_then(Json::as_str) {
+        None | Some("both") => Repeat::Both,
+        Some("horizontal") => Repeat::Horizontal,
+        Some("vertical") => Repeat::Vertical,
+        Some(other) => bail!("未知的重复方向\"{}\"", other),
+    };
+    let scale = match value.get("scale").and_then(Json::as_str) {
+        None | Some("none") => LayerScale::None,
+        Some("stretch") => LayerScale::Stretch,
+        Some("fit_height") => LayerScale::FitHeight,
+        Some("fit_width") => LayerScale::FitWidth,
+        Some(other) => bail!("未知的缩放方式\"{}\"", other),
+    };
+    Ok(BackgroundDesc {
+        texture,
+        viewport,
+        velocity: vector(value.get("velocity"), Vec2::ZERO)?,
+        parallax: vector(value.get("parallax"), Vec2::ONE)?,
+        dest,
+        repeat,
+        scale,
+    })
+}
+
+impl Manifest {
+    /// 解析并验证清单, 错误信息包含出错的项, 例如 animations.timmy: fps必须大于0
+    pub fn parse(text: &str) -> Result<Manifest> {
+        let root = Json::parse(text)?;
+        if root.as_object().is_none() {
+            bail!("清单应为对象");
+        }
+        let mut manifest = Manifest::default();
+        for (key, value) in section(&root, "textures")? {
+            manifest.textures.insert(key.clone(), path(value).with_context(|| format!("textures.{}", key))?);
+        }
+        for (key, value) in section(&root, "animations")? {
+            let animation = parse_animation(value).with_context(|| format!("animations.{}", key))?;
+            manifest.animations.insert(key.clone(), animation);
+        }
+        for (key, value) in section(&root, "sounds")? {
+            manifest.sounds.insert(key.clone(), parse_sound(value).with_context(|| format!("sounds.{}", key))?);
+        }
+        for (key, value) in section(&root, "fonts")? {
+            manifest.fonts.insert(key.clone(), path(value).with_context(|| format!("fonts.{}", key))?);
+        }
+        for (key, value) in section(&root, "backgrounds")? {
+            manifest.backgrounds.insert(key.clone(), parse_background(value).with_context(|| format!("backgrounds.{}", key))?);
+        }
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// 检查动画和背景引用的图片是否存在
+    pub fn validate(&self) -> Result<()> {
+        for (key, animation) in &self.animations {
+            if !self.textures.contains_key(&animation.texture) {
+                bail!("animations.{}: 引用了不存在的图片\"{}\"", key, animation.texture);
+            }
+        }
+        for (key, background) in &self.backgrounds {
+            if !self.textures.contains_key(&background.texture) {
+                bail!("backgrounds.{}: 引用了不存在的图片\"{}\"", key, background.texture);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn load(path: &str) -> Result<Manifest> {
+        let text = embed::load_string(path).await.with_context(|| format!("清单加载失败: {}", path))?;
+        Manifest::parse(&text).with_context(|| format!("清单格式错误: {}", path))
+    }
+
+    /// 添加清单中的图片、声音和字体到资源管理器
+    pub fn queue(&self, assets: &mut AssetManager) -> ManifestHandles {
+        ManifestHandles {
+            textures: self.textures.iter().map(|(k, p)| (k.clone(), assets.queue_texture(k, p))).collect(),
+            sounds: self.sounds.iter().map(|(k, (p, _))| (k.clone(), assets.queue_sound(k, p))).collect(),
+            fonts: self.fonts.iter().map(|(k, p)| (k.clone(), assets.queue_font(k, p))).collect(),
+        }
+    }
+}
+
+/// 清单中资源的句柄
+#[derive(Clone, Debug, Default)]
+pub struct ManifestHandles {
+    pub textures: HashMap<String, TextureHandle>,
+    pub sounds: HashMap<String, SoundAssetHandle>,
+    pub fonts: HashMap<String, FontHandle>,
+}
+
+/// 按清单加载完成的资源
+pub struct Resources {
+    pub manifest: Manifest,
+    /// 清单文件路径
+    path: Option<String>,
+    assets: AssetManager,
+    handles: ManifestHandles,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<FileWatcher>,
+    drawables: HashMap<String, Drawable>,
+    sounds: HashMap<String, SoundHandle>,
+    fonts: HashMap<String, TextFont>,
+}
+
+impl Resources {
+    /// 用加载完成的资源创建, 声音添加到音频管理器
+    pub fn build(manifest: Manifest, handles: ManifestHandles, assets: AssetManager, audio: &mut AudioManager) -> Result<Resources> {
+        let mut drawables = HashMap::new();
+        for (key, handle) in &handles.textures {
+            drawables.insert(key.clone(), assets.drawable(*handle).ok_or_else(|| anyhow!("textures.{}: 没有加载", key))?);
+        }
+        let mut sounds = HashMap::new();
+        for (key, handle) in &handles.sounds {
+            let options = manifest.sounds.get(key).map(|(_, o)| *o).unwrap_or_default();
+            sounds.insert(key.clone(), assets.add_sound_to(audio, *handle, options).ok_or_else(|| anyhow!("sounds.{}: 没有加载", key))?);
+        }
+        let mut fonts = HashMap::new();
+        for (key, handle) in &handles.fonts {
+            fonts.insert(key.clone(), assets.font(*handle).ok_or_else(|| anyhow!("fonts.{}: 没有加载", key))?);
+        }
+        Ok(Resources {
+            manifest,
+            path: None,
+            assets,
+            handles,
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: None,
+            drawables,
+            sounds,
+            fonts,
+        })
+    }
+
+    /// 不创建纹理和声音, 用于无窗口运行(例如回放验证). 图片只读取大小, 使用Drawable::Blank;
+    /// 声音添加到NullBackend, 名字为清单中的键; 不加载字体. 不能用于wasm
+    pub fn headless(manifest: Manifest, audio: &mut AudioManager<crate::audio::NullBackend>) -> Result<Resources> {
+        let mut drawables = HashMap::new();
+        for (key, path) in &manifest.textures {
+            let image = embed::read_file(path).and_then(|data| embed::decode_image(&data, path)).with_context(|| format!("textures.{}", key))?;
+            drawables.insert(key.clone(), Drawable::Blank { width: image.width as f32, height: image.height as f32 });
+        }
+        let sounds = manifest.sounds.iter().map(|(key, (_, options))| (key.clone(), audio.add_sound(key.clone(), *options))).collect();
+        Ok(Resources {
+            manifest,
+            path: None,
+            assets: AssetManager::new(),
+            handles: ManifestHandles::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: None,
+            drawables,
+            sounds,
+            fonts: HashMap::new(),
+        })
+    }
+
+    /// 加载清单和其中所有资源, 加载时显示加载画面, width和height为游戏画面大小
+    pub async fn load(path: &str, width: f32, height: f32, audio: &mut AudioManager) -> Result<Resources> {
+        let manifest = Manifest::load(path).await?;
+        let mut assets = AssetManager::new();
+        let handles = manifest.queue(&mut assets);
+        let assets = LoadingScreen::new(width, height, assets).load().await?;
+        let mut resources = Resources::build(manifest, handles, assets, audio)?;
+        resources.path = Some(path.to_string());
+        Ok(resources)
+    }
+
+    /// 开启热重载, 监视图片和清单文件, 只用于桌面平台
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch(&mut self) {
+        self.assets.watch();
+        let mut watcher = FileWatcher::default();
+        if let Some(path) = &self.path {
+            watcher.watch(path);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    /// 重新加载修改过的图片和清单, 每帧调用一次, 返回的Reload用于替换已创建的精灵和背景中的资源.
+    /// 清单只重新加载动画和背景, 新增的图片、声音和字体需要重新启动
+    pub fn reload(&mut self) -> Reload {
+        let mut reload = self.assets.reload();
+        if !reload.textures.is_empty() {
+            for (key, handle) in &self.handles.textures {
+                if let Some(drawable) = self.assets.drawable(*handle) {
+                    self.drawables.insert(key.clone(), drawable);
+                }
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(watcher), Some(path)) = (self.watcher.as_mut(), self.path.as_ref()) {
+            if !watcher.changed().is_empty() {
+                match std::fs::read_to_string(path).map_err(anyhow::Error::from).and_then(|text| Manifest::parse(&text)) {
+                    Ok(manifest) => reload.append(self.reload_manifest(manifest)),
+                    Err(err) => warn!("清单重新加载失败: {}: {:#}", path, err),
+                }
+            }
+        }
+        reload
+    }
+
+    /// 替换清单中的动画和背景
+    pub fn reload_manifest(&mut self, manifest: Manifest) -> Reload {
+        let mut reload = Reload::default();
+        let missing = manifest.animations.values().map(|a| &a.texture)
+            .chain(manifest.backgrounds.values().map(|b| &b.texture))
+            .find(|texture| !self.drawables.contains_key(*texture));
+        if let Some(texture) = missing {
+            warn!("清单引用了没有加载的图片\"{}\", 需要重新启动", texture);
+            return reload;
+        }
+        for (key, animation) in &manifest.animations {
+            if let (Some(old), Some(Drawable::Texture2D(texture))) = (self.manifest.animations.get(key), self.drawables.get(&animation.texture)) {
+                if old.texture == animation.texture && old.frames != animation.frames {
+                    reload.animations.push((*texture, old.frames.clone(), animation.frames.clone()));
+                }
+            }
+        }
+        self.manifest.animations = manifest.animations;
+        self.manifest.backgrounds = manifest.backgrounds;
+        reload
+    }
+
+    pub fn drawable(&self, key: &str) -> Option<Drawable> {
+        self.drawables.get(key).cloned()
+    }
+
+    pub fn sound(&self, key: &str) -> Option<SoundHandle> {
+        self.sounds.get(key).copied()
+    }
+
+    pub fn font(&self, key: &str) -> Option<TextFont> {
+        self.fonts.get(key).cloned()
+    }
+
+    /// 创建动画, 每次调用返回新的动画
+    pub fn animation(&self, key: &str) -> Option<Animation> {
+        let desc = self.manifest.animations.get(key)?;
+        let mut animation = Animation::new(self.drawable(&desc.texture)?, desc.frames.clone(), desc.fps);
+        animation.set_repeat(desc.repeat);
+        Some(animation)
+    }
+
+    /// 创建背景图层
+    pub fn background(&self, key: &str) -> Option<BackgroundLayer> {
+        let desc = self.manifest.backgrounds.get(key)?;
+        let mut layer = BackgroundLayer::with_velocity(self.drawable(&desc.texture)?, desc.viewport, desc.velocity);
+        layer.set_parallax(desc.parallax);
+        layer.set_dest(desc.dest);
+        layer.set_repeat(desc.repeat);
+        layer.set_scale(desc.scale);
+        Some(layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Manifest::parse(r#"{
+            "textures": { "timmy": "static/Timmy.png", "clouds": { "path": "static/Clouds.png" } },
+            "animations": {
+                "timmy": { "texture": "timmy", "fps": 25, "repeat": true, "frames": [[0, 0, 33, 17], [0, 17, 33, 17]] },
+                "walk": { "texture": "timmy", "fps": 10, "grid": { "width": 10, "height": 20, "columns": 2, "count": 3 } }
+            },
+            "sounds": { "missile": { "path": "static/TMissile.ogg", "max_voices": 4, "length": 0.5 }, "theme": { "path": "a.wav", "group": "music" } },
+            "fonts": { "hint": "static/font.ttf" },
+            "backgrounds": { "clouds": { "texture": "clouds", "viewport": [0, 0, 600, 450], "velocity": [1, 0], "repeat": "horizontal" } }
+        }"#).unwrap();
+        assert_eq!(manifest.textures["clouds"], "static/Clouds.png");
+        assert_eq!(manifest.animations["timmy"].frames[1], Rect::new(0., 17., 33., 17.));
+        assert_eq!(manifest.animations["walk"].frames, vec![Rect::new(0., 0., 10., 20.), Rect::new(10., 0., 10., 20.), Rect::new(0., 20., 10., 20.)]);
+        assert_eq!(manifest.sounds["missile"].1.max_voices, 4);
+        assert_eq!(manifest.sounds["theme"].1.group, AudioGroup::Music);
+        assert_eq!(manifest.backgrounds["clouds"].repeat, Repeat::Horizontal);
+        assert_eq!(manifest.backgrounds["clouds"].parallax, Vec2::ONE);
+
+        let err = |text: &str| format!("{:#}", Manifest::parse(text).unwrap_err());
+        assert_eq!(err(r#"{ "animations": { "timmy": { "texture": "timmy", "fps": 0, "frames": [] } } }"#), "animations.timmy: fps必须大于0");
+        assert_eq!(err(r#"{ "animations": { "timmy": { "texture": "timmy", "fps": 1, "frames": [[0, 0, 1, 1]] } } }"#), "animations.timmy: 引用了不存在的图片\"timmy\"");
+        assert_eq!(err(r#"{ "sounds": { "boom": "boom.mp3" } }"#), "sounds.boom: 不支持的音频格式MP3");
+        assert_eq!(err(r#"{ "textures": { "a": "a.png", } }"#), "JSON格式错误(第1行): 缺少字段名");
+    }
+}
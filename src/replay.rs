@@ -0,0 +1,310 @@
+use std::fmt;
+
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::{prelude::{KeyCode, MouseButton}, rand::srand};
+
+use crate::{current_timestamp, embed, engine::GameEngine, input::{parse_key, parse_mouse_button}, set_game_time, Event, Flow, KeyMods, State};
+
+//输入录制和回放: 记录随机数种子和每次更新前收到的事件, 回放时在相同的更新次数发送, 重现整个游戏过程.
+//录制和回放时事件和更新中使用按更新次数计算的游戏时间(见set_game_time), 游戏逻辑不能依赖其他系统时间.
+//run()在GameEngine::initialize之前设置随机数种子, 随机创建的精灵要在initialize中创建.
+//
+// 录制: Settings{ record: Some("session.replay".into()), .. }, run()结束时保存
+// 回放: Settings{ replay: Some("session.replay".into()), .. }, 回放结束后恢复正常输入
+// 无窗口验证: replay::verify(&mut game, &Recording::parse(&text)?), 比较结束时的精灵状态
+
+const HEADER: &str = "mengine-replay 1";
+
+/// 录制的游戏过程
+///
+/// mengine-replay 1
+/// seed 16876543210
+/// ups 60
+/// 0 Resize 600 450
+/// 12 KeyDown Space - false
+/// ticks 600
+/// sprite timmy 309 20 33 17 3 0
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    /// run()传给srand的随机数种子
+    pub seed: u64,
+    pub ups: u64,
+    /// (更新次数, 事件), 事件在第n次更新之前发送
+    pub events: Vec<(u64, Event)>,
+    /// 总更新次数
+    pub ticks: u64,
+    /// 结束时的精灵状态, 见sprite_state
+    pub sprites: Vec<String>,
+}
+
+impl Recording {
+    pub fn new(seed: u64, ups: u64) -> Recording {
+        Recording { seed, ups, ..Default::default() }
+    }
+
+    pub fn record(&mut self, tick: u64, event: Event) {
+        self.events.push((tick, event));
+    }
+
+    /// 第tick次更新时的游戏时间(ms)
+    pub fn game_time(&self, start: f64, tick: u64) -> f64 {
+        start + tick as f64 * 1000. / self.ups as f64
+    }
+
+    pub fn parse(text: &str) -> Result<Recording> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            bail!("不是录制文件");
+        }
+        let mut recording = Recording::default();
+        for (i, line) in lines {
+            parse_line(&mut recording, line.trim()).with_context(|| format!("第{}行", i + 1))?;
+        }
+        if recording.ups == 0 {
+            bail!("缺少ups");
+        }
+        Ok(recording)
+    }
+
+    pub async fn load(path: &str) -> Result<Recording> {
+        Recording::parse(&embed::load_string(path).await?).with_context(|| format!("录制文件加载失败: {}", path))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_string()).with_context(|| format!("录制文件保存失败: {}", path))
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "ups {}", self.ups)?;
+        for (tick, event) in &self.events {
+            write!(f, "{} ", tick)?;
+            write_event(f, event)?;
+            writeln!(f)?;
+        }
+        writeln!(f, "ticks {}", self.ticks)?;
+        for sprite in &self.sprites {
+            writeln!(f, "sprite {}", sprite)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_modifiers(f: &mut fmt::Formatter, modifiers: &KeyMods) -> fmt::Result {
+    let names: Vec<&str> = [("shift", modifiers.shift), ("ctrl", modifiers.ctrl), ("alt", modifiers.alt), ("logo", modifiers.logo)]
+        .iter().filter(|(_, down)| *down).map(|(name, _)| *name).collect();
+    if names.is_empty() {
+        write!(f, "-")
+    } else {
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+fn write_event(f: &mut fmt::Formatter, event: &Event) -> fmt::Result {
+    match event {
+        Event::MouseMove(x, y) => write!(f, "MouseMove {} {}", x, y),
+        Event::MouseDown(button, x, y) => write!(f, "MouseDown {:?} {} {}", button, x, y),
+        Event::MouseUp(button, x, y) => write!(f, "MouseUp {:?} {} {}", button, x, y),
+        Event::MouseWheel(x, y) => write!(f, "MouseWheel {} {}", x, y),
+        Event::TouchStart(id, x, y) => write!(f, "TouchStart {} {} {}", id, x, y),
+        Event::TouchMove(id, x, y) => write!(f, "TouchMove {} {} {}", id, x, y),
+        Event::TouchEnd(id, x, y) => write!(f, "TouchEnd {} {} {}", id, x, y),
+        //字符按编码保存, 避免空白字符
+        Event::Text(c) => write!(f, "Text {}", *c as u32),
+        Event::KeyDown { key, modifiers, repeat } => {
+            write!(f, "KeyDown {:?} ", key)?;
+            write_modifiers(f, modifiers)?;
+            write!(f, " {}", repeat)
+        }
+        Event::KeyUp { key, modifiers } => {
+            write!(f, "KeyUp {:?} ", key)?;
+            write_modifiers(f, modifiers)
+        }
+        Event::Resize(width, height) => write!(f, "Resize {} {}", width, height),
+        Event::Stalled { ms } => write!(f, "Stalled {}", ms),
+    }
+}
+
+fn parse_line(recording: &mut Recording, line: &str) -> Result<()> {
+    let (first, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match first {
+        "seed" => recording.seed = rest.parse()?,
+        "ups" => recording.ups = rest.parse()?,
+        "ticks" => recording.ticks = rest.parse()?,
+        "sprite" => recording.sprites.push(rest.to_string()),
+        tick => {
+            let tick = tick.parse().map_err(|_| anyhow!("无效的内容: {}", line))?;
+            if recording.events.last().map(|(t, _)| *t > tick).unwrap_or(false) {
+                bail!("事件没有按更新次数排列");
+            }
+            recording.events.push((tick, parse_event(rest)?));
+        }
+    }
+    Ok(())
+}
+
+fn parse_modifiers(text: &str) -> Result<KeyMods> {
+    let mut modifiers = KeyMods::default();
+    if text == "-" {
+        return Ok(modifiers);
+    }
+    for name in text.split('+') {
+        match name {
+            "shift" => modifiers.shift = true,
+            "ctrl" => modifiers.ctrl = true,
+            "alt" => modifiers.alt = true,
+            "logo" => modifiers.logo = true,
+            _ => bail!("无效的修饰键: {}", name),
+        }
+    }
+    Ok(modifiers)
+}
+
+fn parse_event(text: &str) -> Result<Event> {
+    let args: Vec<&str> = text.split_whitespace().collect();
+    let float = |i: usize| -> Result<f32> { args.get(i).ok_or_else(|| anyhow!("缺少参数: {}", text))?.parse().map_err(|_| anyhow!("无效的数字: {}", text)) };
+    let id = || -> Result<u64> { args.get(1).ok_or_else(|| anyhow!("缺少参数: {}", text))?.parse().map_err(|_| anyhow!("无效的触点: {}", text)) };
+    let key = || -> Result<KeyCode> { args.get(1).and_then(|name| parse_key(name)).ok_or_else(|| anyhow!("无效的按键: {}", text)) };
+    let button = || -> Result<MouseButton> { args.get(1).and_then(|name| parse_mouse_button(name)).ok_or_else(|| anyhow!("无效的鼠标按键: {}", text)) };
+    let modifiers = || parse_modifiers(args.get(2).copied().unwrap_or("-"));
+    let event = match args.first().copied() {
+        Some("MouseMove") => Event::MouseMove(float(1)?, float(2)?),
+        Some("MouseDown") => Event::MouseDown(button()?, float(2)?, float(3)?),
+        Some("MouseUp") => Event::MouseUp(button()?, float(2)?, float(3)?),
+        Some("MouseWheel") => Event::MouseWheel(float(1)?, float(2)?),
+        Some("TouchStart") => Event::TouchStart(id()?, float(2)?, float(3)?),
+        Some("TouchMove") => Event::TouchMove(id()?, float(2)?, float(3)?),
+        Some("TouchEnd") => Event::TouchEnd(id()?, float(2)?, float(3)?),
+        Some("Text") => Event::Text(args.get(1).and_then(|c| c.parse().ok()).and_then(char::from_u32).ok_or_else(|| anyhow!("无效的字符: {}", text))?),
+        Some("KeyDown") => Event::KeyDown { key: key()?, modifiers: modifiers()?, repeat: args.get(3) == Some(&"true") },
+        Some("KeyUp") => Event::KeyUp { key: key()?, modifiers: modifiers()? },
+        Some("Resize") => Event::Resize(float(1)?, float(2)?),
+        Some("Stalled") => Event::Stalled { ms: float(1)? },
+        _ => bail!("无效的事件: {}", text),
+    };
+    Ok(event)
+}
+
+/// 精灵状态, 每个精灵一行: 名字 x y 宽 高 速度x 速度y
+pub fn sprite_state(engine: &dyn GameEngine) -> Vec<String> {
+    engine.sprites().iter().map(|sprite| {
+        let (position, velocity) = (sprite.position(), sprite.velocity());
+        format!("{} {} {} {} {} {} {}", sprite.name(), position.x, position.y, position.w, position.h, velocity.x, velocity.y)
+    }).collect()
+}
+
+/// 按更新次数回放录制的事件
+pub struct Player {
+    recording: Recording,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(recording: Recording) -> Player {
+        Player { recording, next: 0 }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// 已回放到录制结束
+    pub fn is_done(&self, tick: u64) -> bool {
+        tick >= self.recording.ticks
+    }
+
+    /// 第tick次更新之前的事件
+    pub fn events(&mut self, tick: u64) -> Vec<Event> {
+        let mut events = vec![];
+        while let Some((t, event)) = self.recording.events.get(self.next) {
+            if *t > tick {
+                break;
+            }
+            events.push(*event);
+            self.next += 1;
+        }
+        events
+    }
+}
+
+/// 不打开窗口回放, 只调用State::event和State::update, 返回结束时的精灵状态.
+/// State在update中不能绘图或使用macroquad的纹理和声音
+pub fn run_headless<T: State>(state: &mut T, recording: &Recording) -> Result<Vec<String>> {
+    srand(recording.seed);
+    if !state.initialize() {
+        bail!("游戏引擎初始化失败");
+    }
+    let start = current_timestamp();
+    let mut player = Player::new(recording.clone());
+    let mut result = Ok(());
+    for tick in 0..recording.ticks {
+        set_game_time(Some(recording.game_time(start, tick)));
+        for event in player.events(tick) {
+            state.event(event);
+        }
+        match state.update() {
+            Ok(Flow::Continue) => (),
+            Ok(Flow::Quit) => break,
+            Ok(Flow::Scene(_)) => {
+                result = Err(anyhow!("场景切换需要使用SceneStack"));
+                break;
+            }
+            Err(err) => {
+                result = Err(err.context(format!("第{}次更新出错", tick)));
+                break;
+            }
+        }
+    }
+    set_game_time(None);
+    let sprites = state.engine().map(|engine| sprite_state(engine)).unwrap_or_default();
+    state.end();
+    result.map(|_| sprites)
+}
+
+/// 无窗口回放并比较结束时的精灵状态
+pub fn verify<T: State>(state: &mut T, recording: &Recording) -> Result<()> {
+    let sprites = run_headless(state, recording)?;
+    for (i, (recorded, replayed)) in recording.sprites.iter().zip(&sprites).enumerate() {
+        if recorded != replayed {
+            bail!("第{}个精灵状态不同, 录制: {}, 回放: {}", i + 1, recorded, replayed);
+        }
+    }
+    if recording.sprites.len() != sprites.len() {
+        bail!("精灵数量不同, 录制: {}, 回放: {}", recording.sprites.len(), sprites.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording() {
+        let mut recording = Recording::new(42, 60);
+        recording.record(0, Event::Resize(600., 450.));
+        recording.record(3, Event::KeyDown { key: KeyCode::Space, modifiers: KeyMods { shift: true, ctrl: true, ..Default::default() }, repeat: false });
+        recording.record(3, Event::Text('a'));
+        recording.record(3, Event::Text(' '));
+        recording.record(5, Event::MouseDown(MouseButton::Left, 1.5, -0.25));
+        recording.record(5, Event::TouchEnd(7, 0.1, 0.2));
+        recording.record(6, Event::KeyUp { key: KeyCode::Space, modifiers: KeyMods::default() });
+        recording.record(6, Event::Stalled { ms: 612.5 });
+        recording.ticks = 10;
+        recording.sprites.push("timmy 309 20 33 17 3 0".to_string());
+
+        let text = recording.to_string();
+        assert!(text.contains("\n3 KeyDown Space shift+ctrl false\n3 Text 97\n3 Text 32\n"));
+        assert_eq!(Recording::parse(&text).unwrap(), recording);
+        assert_eq!(format!("{:#}", Recording::parse("mengine-replay 1\nups 60\n2 Jump").unwrap_err()), "第3行: 无效的事件: Jump");
+
+        let mut player = Player::new(recording);
+        assert_eq!(player.events(0), vec![Event::Resize(600., 450.)]);
+        assert!(player.events(2).is_empty());
+        assert_eq!(player.events(3).len(), 3);
+        assert!(!player.is_done(9) && player.is_done(10));
+    }
+}
@@ -0,0 +1,519 @@
+use macroquad::prelude::{clear_background, draw_rectangle, draw_texture_ex, pop_camera_state, push_camera_state, set_camera, vec2, Camera2D, Color, DrawTextureParams, Image, RenderTarget, Vec2, BLANK, WHITE};
+
+use anyhow::{anyhow, Result};
+
+use crate::{current_timestamp, Flow, effects::{reuse_targets, EffectChain}, engine::GameEngine, Event, State};
+
+//场景栈: 状态可以压入、弹出和替换其他状态, 暂停的状态仍在下面绘制
+
+/// 场景切换的过渡效果
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transition {
+    None,
+    /// 淡出到颜色, 在中间切换场景, 再淡入
+    Fade { color: Color, duration: f32 },
+    /// Push和Replace时新场景从direction方向滑入, 暂停或被替换的场景向相反方向滑出;
+    /// Pop时弹出的场景向相反方向滑出, 下面的场景不动. direction为单位向量, 例如(1, 0)从右边滑入
+    Slide { direction: Vec2, duration: f32 },
+}
+
+impl Transition {
+    pub fn fade(duration: f32) -> Transition {
+        Transition::Fade { color: Color::new(0., 0., 0., 1.), duration }
+    }
+
+    pub fn slide_left(duration: f32) -> Transition {
+        Transition::Slide { direction: vec2(1., 0.), duration }
+    }
+
+    pub fn slide_right(duration: f32) -> Transition {
+        Transition::Slide { direction: vec2(-1., 0.), duration }
+    }
+
+    fn duration(&self) -> f32 {
+        match *self {
+            Transition::None => 0.,
+            Transition::Fade { duration, .. } | Transition::Slide { duration, .. } => duration,
+        }
+    }
+}
+
+/// 场景切换请求, 由State::update返回Flow::Scene
+pub enum SceneChange {
+    /// 压入新场景, 当前场景暂停
+    Push(Box<dyn State>, Transition),
+    /// 弹出当前场景, 恢复下面的场景
+    Pop(Transition),
+    /// 替换当前场景
+    Replace(Box<dyn State>, Transition),
+}
+
+impl SceneChange {
+    fn transition(&self) -> Transition {
+        match self {
+            SceneChange::Push(_, t) | SceneChange::Pop(t) | SceneChange::Replace(_, t) => *t,
+        }
+    }
+}
+
+/// 正在进行的过渡
+struct ActiveTransition {
+    transition: Transition,
+    elapsed: f32,
+    /// 淡入淡出时在中间执行的切换
+    pending: Option<SceneChange>,
+    /// 滑出的场景, 已调用exit, 只用于绘制
+    outgoing: Option<Box<dyn State>>,
+    /// 栈顶是滑入的场景
+    incoming: bool,
+}
+
+/// 场景栈, 本身也是State, 可以直接交给run()
+pub struct SceneStack {
+    scenes: Vec<Box<dyn State>>,
+    transition: Option<ActiveTransition>,
+    width: f32,
+    height: f32,
+    targets: Option<(RenderTarget, RenderTarget)>,
+    last_tick: Option<f64>,
+    /// 进入场景时游戏引擎初始化失败, 由update返回
+    error: Option<anyhow::Error>,
+}
+
+impl SceneStack {
+    /// width和height为游戏画面大小, 用于绘制过渡效果.
+    /// 第一个场景在State::initialize时进入, 由run()在初始化随机数种子之后调用
+    pub fn new(width: f32, height: f32, scene: Box<dyn State>) -> SceneStack {
+        SceneStack {
+            scenes: vec![scene],
+            transition: None,
+            width,
+            height,
+            targets: None,
+            last_tick: None,
+            error: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scenes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub fn top(&mut self) -> Option<&mut Box<dyn State>> {
+        self.scenes.last_mut()
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// 切换场景
+    pub fn change(&mut self, change: SceneChange) {
+        //上一个过渡还没有切换场景时先执行, 避免丢失
+        if let Some(pending) = self.transition.take().and_then(|t| t.pending) {
+            self.apply(pending);
+        }
+        let transition = change.transition();
+        match transition {
+            Transition::None => {
+                self.apply(change);
+            }
+            Transition::Fade { .. } => {
+                self.transition = Some(ActiveTransition { transition, elapsed: 0., pending: Some(change), outgoing: None, incoming: false });
+            }
+            Transition::Slide { .. } => {
+                let incoming = !matches!(change, SceneChange::Pop(_));
+                let outgoing = self.apply(change);
+                self.transition = Some(ActiveTransition { transition, elapsed: 0., pending: None, outgoing, incoming });
+            }
+        }
+    }
+
+    /// 执行切换并调用生命周期方法, 返回离开的场景(已调用exit)
+    fn apply(&mut self, change: SceneChange) -> Option<Box<dyn State>> {
+        match change {
+            SceneChange::Push(scene, _) => {
+                if let Some(top) = self.scenes.last_mut() {
+                    top.pause();
+                }
+                self.enter_scene(scene);
+                None
+            }
+            SceneChange::Pop(_) => {
+                let mut scene = self.scenes.pop()?;
+                Self::exit_scene(scene.as_mut());
+                if let Some(top) = self.scenes.last_mut() {
+                    top.resume();
+                }
+                Some(scene)
+            }
+            SceneChange::Replace(new, _) => {
+                let old = self.scenes.pop().map(|mut old| {
+                    Self::exit_scene(old.as_mut());
+                    old
+                });
+                self.enter_scene(new);
+                old
+            }
+        }
+    }
+
+    /// 初始化场景的游戏引擎, 再调用enter
+    fn enter_scene(&mut self, mut scene: Box<dyn State>) {
+        if let Some(engine) = scene.engine() {
+            if !engine.initialize() && self.error.is_none() {
+                self.error = Some(anyhow!("游戏引擎初始化失败"));
+            }
+        }
+        scene.enter();
+        self.scenes.push(scene);
+    }
+
+    /// 调用exit, 再结束场景的游戏引擎
+    fn exit_scene(scene: &mut dyn State) {
+        scene.exit();
+        if let Some(engine) = scene.engine() {
+            engine.end();
+        }
+    }
+
+    /// 推进过渡(秒)
+    pub fn advance(&mut self, dt: f32) {
+        let active = match self.transition.as_mut() {
+            Some(active) => active,
+            None => return,
+        };
+        let duration = active.transition.duration();
+        active.elapsed += dt;
+        if active.elapsed >= duration / 2. {
+            if let Some(change) = active.pending.take() {
+                self.apply(change);
+            }
+        }
+        if self.transition.as_ref().map(|a| a.elapsed >= duration).unwrap_or(false) {
+            self.transition = None;
+        }
+    }
+
+    /// 过渡进度 0.0~1.0
+    pub fn progress(&self) -> Option<f32> {
+        self.transition.as_ref().map(|a| {
+            let duration = a.transition.duration();
+            if duration > 0. { (a.elapsed / duration).min(1.) } else { 1. }
+        })
+    }
+
+    /// 修改游戏画面大小, 滑动过渡使用的渲染目标在下次绘制时重新创建
+    pub fn set_size(&mut self, width: f32, height: f32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn targets(&mut self) -> (RenderTarget, RenderTarget) {
+        reuse_targets(&mut self.targets, self.width, self.height)
+    }
+
+    /// 把场景绘制到纹理再按偏移绘制
+    fn draw_offset(scene: &mut dyn State, target: RenderTarget, offset: Vec2, width: f32, height: f32) -> Result<()> {
+        push_camera_state();
+        set_camera(&Camera2D {
+            zoom: vec2(2. / width, 2. / height),
+            target: vec2(width / 2., height / 2.),
+            render_target: Some(target),
+            ..Default::default()
+        });
+        clear_background(BLANK);
+        let result = scene.draw();
+        pop_camera_state();
+        draw_texture_ex(target.texture, offset.x, offset.y, WHITE, DrawTextureParams {
+            dest_size: Some(vec2(width, height)),
+            ..Default::default()
+        });
+        result
+    }
+}
+
+impl State for SceneStack {
+    /// 过渡期间丢弃按下事件, 松开、停顿和窗口大小事件仍然发给栈顶场景, 避免按键卡住
+    fn event(&mut self, event: Event) {
+        if self.transition.is_some() && !matches!(event, Event::KeyUp { .. } | Event::MouseUp(..) | Event::TouchEnd(..) | Event::Stalled { .. } | Event::Resize(..)) {
+            return;
+        }
+        if let Some(top) = self.scenes.last_mut() {
+            top.event(event);
+        }
+    }
+
+    /// 所有场景弹出后返回Flow::Quit
+    fn update(&mut self) -> Result<Flow> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.transition.is_some() {
+            let now = current_timestamp() / 1000.;
+            let dt = self.last_tick.map(|t| now - t).unwrap_or(0.);
+            self.last_tick = Some(now);
+            self.advance(dt as f32);
+        } else {
+            self.last_tick = None;
+            let flow = match self.scenes.last_mut() {
+                Some(top) => top.update()?,
+                None => Flow::Quit,
+            };
+            match flow {
+                Flow::Continue => (),
+                Flow::Quit => return Ok(Flow::Quit),
+                Flow::Scene(change) => self.change(change),
+            }
+        }
+        Ok(if self.scenes.is_empty() && self.transition.is_none() { Flow::Quit } else { Flow::Continue })
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let (width, height) = (self.width, self.height);
+        let progress = self.progress().unwrap_or(1.);
+        let (transition, incoming) = match self.transition.as_ref() {
+            Some(active) => (active.transition, active.incoming),
+            None => (Transition::None, false),
+        };
+        let direction = match transition {
+            Transition::Slide { direction, .. } => direction * vec2(width, height),
+            _ => Vec2::ZERO,
+        };
+        //暂停的场景在下面绘制, Push滑动时刚暂停的场景滑出
+        let below = if incoming { self.scenes.len() - 1 } else { self.scenes.len() };
+        let has_outgoing = self.transition.as_ref().map(|t| t.outgoing.is_some()).unwrap_or(false);
+        let paused = if incoming && !has_outgoing && below > 0 { Some(below - 1) } else { None };
+        for scene in self.scenes[..paused.unwrap_or(below)].iter_mut() {
+            scene.draw()?;
+        }
+        if let Transition::Slide { .. } = transition {
+            let (a, b) = self.targets();
+            if let Some(outgoing) = self.transition.as_mut().and_then(|t| t.outgoing.as_mut()) {
+                Self::draw_offset(outgoing.as_mut(), a, -direction * progress, width, height)?;
+            }
+            if let Some(i) = paused {
+                Self::draw_offset(self.scenes[i].as_mut(), a, -direction * progress, width, height)?;
+            }
+            if incoming {
+                if let Some(top) = self.scenes.last_mut() {
+                    Self::draw_offset(top.as_mut(), b, direction * (1. - progress), width, height)?;
+                }
+            }
+        }
+        if let Transition::Fade { color, .. } = transition {
+            let alpha = 1. - (progress * 2. - 1.).abs();
+            draw_rectangle(0., 0., width, height, Color::new(color.r, color.g, color.b, color.a * alpha));
+        }
+        Ok(())
+    }
+
+    fn hot_reload(&mut self) {
+        for scene in self.scenes.iter_mut() {
+            scene.hot_reload();
+        }
+    }
+
+    fn wants_screenshot(&mut self) -> bool {
+        self.scenes.last_mut().map(|s| s.wants_screenshot()).unwrap_or(false)
+    }
+
+    fn screenshot(&mut self, image: Image) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.screenshot(image);
+        }
+    }
+
+    fn effects(&mut self) -> Option<&mut EffectChain> {
+        self.scenes.last_mut().and_then(|s| s.effects())
+    }
+
+    /// 栈顶场景的游戏引擎, 场景的GameEngine::initialize和end由场景栈在进入和离开时调用
+    fn engine(&mut self) -> Option<&mut dyn GameEngine> {
+        self.scenes.last_mut().and_then(|s| s.engine())
+    }
+
+    fn initialize(&mut self) -> bool {
+        if let Some(scene) = self.scenes.pop() {
+            self.enter_scene(scene);
+        }
+        self.error.take().is_none()
+    }
+
+    fn end(&mut self) {
+        self.exit();
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.scenes.last_mut().map(|s| s.quit_requested()).unwrap_or(true)
+    }
+
+    fn enter(&mut self) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.enter();
+        }
+    }
+
+    /// 从栈顶开始依次退出所有场景, 滑出的场景已经退出
+    fn exit(&mut self) {
+        self.transition = None;
+        if let Some((a, b)) = self.targets.take() {
+            a.delete();
+            b.delete();
+        }
+        while let Some(mut scene) = self.scenes.pop() {
+            Self::exit_scene(scene.as_mut());
+        }
+    }
+
+    fn pause(&mut self) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.pause();
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(top) = self.scenes.last_mut() {
+            top.resume();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use macroquad::prelude::KeyCode;
+
+    use crate::{engine::Sprite, KeyMods};
+
+    use super::*;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    struct TestScene {
+        name: &'static str,
+        log: Log,
+        sprites: Vec<Sprite>,
+    }
+
+    impl TestScene {
+        fn boxed(name: &'static str, log: &Log) -> Box<dyn State> {
+            Box::new(TestScene { name, log: log.clone(), sprites: vec![] })
+        }
+
+        fn push(&self, event: &str) {
+            self.log.borrow_mut().push(format!("{} {}", self.name, event));
+        }
+    }
+
+    impl GameEngine for TestScene {
+        fn sprites_mut(&mut self) -> &mut Vec<Sprite> {
+            &mut self.sprites
+        }
+        fn sprites(&self) -> &Vec<Sprite> {
+            &self.sprites
+        }
+        fn sprite_dying(&mut self, _: usize) {}
+        fn sprite_collision(&mut self, _: usize, _: usize) -> bool {
+            false
+        }
+        fn initialize(&mut self) -> bool {
+            self.push("initialize");
+            self.name != "broken"
+        }
+        fn end(&self) {
+            self.push("end");
+        }
+    }
+
+    impl State for TestScene {
+        fn event(&mut self, event: Event) {
+            self.push(&format!("{:?}", event));
+        }
+        fn update(&mut self) -> Result<Flow> {
+            self.push("update");
+            Ok(match self.name {
+                "title" => Flow::Scene(SceneChange::Replace(TestScene::boxed("game", &self.log), Transition::None)),
+                "game" => Flow::Quit,
+                _ => Flow::Continue,
+            })
+        }
+        fn draw(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn enter(&mut self) {
+            self.push("enter");
+        }
+        fn exit(&mut self) {
+            self.push("exit");
+        }
+        fn pause(&mut self) {
+            self.push("pause");
+        }
+        fn resume(&mut self) {
+            self.push("resume");
+        }
+        fn engine(&mut self) -> Option<&mut dyn GameEngine> {
+            Some(self)
+        }
+    }
+
+    #[test]
+    fn test_scene_stack() {
+        let log: Log = Rc::new(RefCell::new(vec![]));
+        let mut stack = SceneStack::new(100., 100., TestScene::boxed("title", &log));
+        assert!(log.borrow().is_empty());
+        assert!(stack.initialize());
+        assert!(matches!(stack.update().unwrap(), Flow::Continue));
+        stack.change(SceneChange::Push(TestScene::boxed("pause", &log), Transition::None));
+        assert_eq!(stack.len(), 2);
+        stack.change(SceneChange::Pop(Transition::fade(1.)));
+        assert!(stack.is_transitioning());
+        //过渡期间只转发松开和窗口大小事件
+        stack.event(Event::KeyDown { key: KeyCode::A, modifiers: KeyMods::default(), repeat: false });
+        stack.event(Event::KeyUp { key: KeyCode::A, modifiers: KeyMods::default() });
+        stack.event(Event::Resize(200., 100.));
+        //淡入淡出在中间切换
+        stack.advance(0.4);
+        assert_eq!(stack.len(), 2);
+        stack.advance(0.2);
+        assert_eq!(stack.len(), 1);
+        stack.advance(0.5);
+        assert!(!stack.is_transitioning());
+        stack.change(SceneChange::Push(TestScene::boxed("pause", &log), Transition::None));
+        //结束时退出整个场景栈
+        stack.end();
+        assert!(stack.is_empty());
+        assert_eq!(*log.borrow(), vec![
+            "title initialize", "title enter", "title update", "title exit", "title end", "game initialize", "game enter",
+            "game pause", "pause initialize", "pause enter",
+            "pause KeyUp { key: A, modifiers: KeyMods { shift: false, ctrl: false, alt: false, logo: false } }", "pause Resize(200.0, 100.0)",
+            "pause exit", "pause end", "game resume",
+            "game pause", "pause initialize", "pause enter", "pause exit", "pause end", "game exit", "game end",
+        ]);
+
+        //淡入淡出还没切换时开始新的场景切换, 先执行之前的切换
+        log.borrow_mut().clear();
+        let mut stack = SceneStack::new(100., 100., TestScene::boxed("menu", &log));
+        assert!(stack.initialize());
+        stack.change(SceneChange::Push(TestScene::boxed("pause", &log), Transition::fade(1.)));
+        stack.change(SceneChange::Replace(TestScene::boxed("shop", &log), Transition::None));
+        assert!(!stack.is_transitioning());
+        assert_eq!(stack.len(), 2);
+        assert_eq!(*log.borrow(), vec![
+            "menu initialize", "menu enter", "menu pause", "pause initialize", "pause enter",
+            "pause exit", "pause end", "shop initialize", "shop enter",
+        ]);
+
+        //进入场景时初始化失败, 由update返回错误
+        let mut stack = SceneStack::new(100., 100., TestScene::boxed("menu", &log));
+        assert!(stack.initialize());
+        stack.change(SceneChange::Push(TestScene::boxed("broken", &log), Transition::None));
+        assert!(stack.update().is_err());
+    }
+}
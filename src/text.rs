@@ -0,0 +1,800 @@
+use std::{collections::HashMap, rc::Rc};
+
+use anyhow::{anyhow, Context, Result};
+use macroquad::prelude::*;
+
+use crate::embed;
+
+//文字绘制: 字体、测量、对齐、自动换行、富文本和BMFont位图字体
+
+/// 字体句柄
+#[derive(Clone, Debug, Default)]
+pub enum TextFont {
+    /// macroquad默认字体
+    #[default]
+    Default,
+    Ttf(Font),
+    /// BMFont位图字体
+    Bitmap(Rc<BitmapFont>),
+}
+
+impl PartialEq for TextFont {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TextFont::Default, TextFont::Default) => true,
+            (TextFont::Ttf(a), TextFont::Ttf(b)) => a == b,
+            (TextFont::Bitmap(a), TextFont::Bitmap(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// 加载字体, .fnt文件按BMFont位图字体加载, 其他按TTF加载
+pub async fn load_font(path: &str) -> Result<TextFont> {
+    if path.to_ascii_lowercase().ends_with(".fnt") {
+        Ok(TextFont::Bitmap(Rc::new(BitmapFont::load(path).await?)))
+    } else {
+        let data = embed::load_file(path).await?;
+        let font = load_ttf_font_from_bytes(&data).map_err(|e| anyhow!("{:?}", e)).with_context(|| format!("字体加载失败: {}", path))?;
+        Ok(TextFont::Ttf(font))
+    }
+}
+
+/// 字体度量, 布局时使用
+pub trait FontMetrics {
+    /// 文字宽度
+    fn text_width(&self, text: &str, size: u16) -> f32;
+    /// 在prev后面加上字符c增加的宽度, 布局时逐字累加
+    fn advance(&self, _prev: Option<char>, c: char, size: u16) -> f32 {
+        self.text_width(c.encode_utf8(&mut [0; 4]), size)
+    }
+    /// 行高
+    fn line_height(&self, size: u16) -> f32;
+    /// 行顶部到基线的距离
+    fn baseline(&self, size: u16) -> f32;
+}
+
+impl TextFont {
+    fn ttf(&self) -> Option<Font> {
+        match self {
+            TextFont::Ttf(font) => Some(*font),
+            _ => None,
+        }
+    }
+}
+
+impl FontMetrics for TextFont {
+    fn text_width(&self, text: &str, size: u16) -> f32 {
+        match self {
+            TextFont::Bitmap(font) => font.text_width(text, size),
+            _ => measure_text(text, self.ttf(), size, 1.0).width,
+        }
+    }
+
+    fn advance(&self, prev: Option<char>, c: char, size: u16) -> f32 {
+        match self {
+            TextFont::Bitmap(font) => font.advance(prev, c, size),
+            _ => measure_text(c.encode_utf8(&mut [0; 4]), self.ttf(), size, 1.0).width,
+        }
+    }
+
+    fn line_height(&self, size: u16) -> f32 {
+        match self {
+            TextFont::Bitmap(font) => font.line_height(size),
+            _ => size as f32,
+        }
+    }
+
+    fn baseline(&self, size: u16) -> f32 {
+        match self {
+            TextFont::Bitmap(font) => font.baseline(size),
+            _ => measure_text("M", self.ttf(), size, 1.0).offset_y,
+        }
+    }
+}
+
+/// 对齐方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// 文字样式
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub font: TextFont,
+    pub size: u16,
+    pub color: Color,
+    pub align: Align,
+    /// 额外行距
+    pub line_spacing: f32,
+    /// 解析[color=..][size=..]标记
+    pub markup: bool,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            font: TextFont::Default,
+            size: 16,
+            color: WHITE,
+            align: Align::Left,
+            line_spacing: 0.,
+            markup: true,
+        }
+    }
+}
+
+/// 一段相同颜色和字号的文字
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub color: Color,
+    pub size: u16,
+}
+
+/// 解析富文本标记: [color=#rrggbb]..[/color], [color=red]..[/color], [size=24]..[/size], [[ 表示字符'['.
+/// 无法识别的标记按原样输出
+pub fn parse_markup(text: &str, color: Color, size: u16) -> Vec<TextSpan> {
+    let mut spans: Vec<TextSpan> = vec![];
+    let mut colors = vec![color];
+    let mut sizes = vec![size];
+    let mut current = String::new();
+    let mut rest = text;
+    let flush = |current: &mut String, spans: &mut Vec<TextSpan>, color: Color, size: u16| {
+        if !current.is_empty() {
+            spans.push(TextSpan { text: std::mem::take(current), color, size });
+        }
+    };
+    while let Some(pos) = rest.find('[') {
+        current.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(r) = rest.strip_prefix("[[") {
+            current.push('[');
+            rest = r;
+            continue;
+        }
+        let tag = rest.find(']').map(|end| (&rest[1..end], end));
+        let (color_now, size_now) = (*colors.last().unwrap(), *sizes.last().unwrap());
+        match tag {
+            Some((tag, end)) if parse_tag(tag).is_some() => {
+                flush(&mut current, &mut spans, color_now, size_now);
+                match parse_tag(tag).unwrap() {
+                    Tag::Color(c) => colors.push(c),
+                    Tag::Size(s) => sizes.push(s),
+                    Tag::EndColor => {
+                        if colors.len() > 1 {
+                            colors.pop();
+                        }
+                    }
+                    Tag::EndSize => {
+                        if sizes.len() > 1 {
+                            sizes.pop();
+                        }
+                    }
+                }
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                current.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    current.push_str(rest);
+    flush(&mut current, &mut spans, *colors.last().unwrap(), *sizes.last().unwrap());
+    spans
+}
+
+enum Tag {
+    Color(Color),
+    Size(u16),
+    EndColor,
+    EndSize,
+}
+
+fn parse_tag(tag: &str) -> Option<Tag> {
+    match tag {
+        "/color" => Some(Tag::EndColor),
+        "/size" => Some(Tag::EndSize),
+        _ => {
+            if let Some(v) = tag.strip_prefix("color=") {
+                parse_color(v).map(Tag::Color)
+            } else if let Some(v) = tag.strip_prefix("size=") {
+                v.parse().ok().map(Tag::Size)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// 解析颜色: #rrggbb, #rrggbbaa 或颜色名
+pub fn parse_color(v: &str) -> Option<Color> {
+    if let Some(hex) = v.strip_prefix('#') {
+        let c = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return match hex.len() {
+            6 => Some(Color::from_rgba(c(0)?, c(2)?, c(4)?, 255)),
+            8 => Some(Color::from_rgba(c(0)?, c(2)?, c(4)?, c(6)?)),
+            _ => None,
+        };
+    }
+    Some(match v {
+        "white" => WHITE,
+        "black" => BLACK,
+        "red" => RED,
+        "green" => GREEN,
+        "blue" => BLUE,
+        "yellow" => YELLOW,
+        "orange" => ORANGE,
+        "gray" => GRAY,
+        "pink" => PINK,
+        "purple" => PURPLE,
+        _ => return None,
+    })
+}
+
+/// 布局后的一段文字, x相对于行首
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaidRun {
+    pub text: String,
+    pub color: Color,
+    pub size: u16,
+    pub x: f32,
+    pub width: f32,
+}
+
+/// 布局后的一行文字
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaidLine {
+    pub runs: Vec<LaidRun>,
+    /// 对齐后的行首x坐标
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// 行顶部到基线的距离
+    pub baseline: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextLayout {
+    pub lines: Vec<LaidLine>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// 中日韩文字, 每个字前后都可以换行
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF | 0x2E80..=0x2FFF | 0x3000..=0x30FF | 0x3100..=0x31FF
+        | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF | 0x20000..=0x2FFFF)
+}
+
+/// 不能出现在行首的标点
+fn no_break_before(c: char) -> bool {
+    "，。、！？；：）」』》〉】’”…,.!?;:)]}%".contains(c)
+}
+
+/// 不能出现在行尾的标点
+fn no_break_after(c: char) -> bool {
+    "（「『《〈【‘“([{".contains(c)
+}
+
+fn can_break(prev: char, next: char) -> bool {
+    if no_break_before(next) || no_break_after(prev) {
+        return false;
+    }
+    prev.is_whitespace() || next.is_whitespace() || is_cjk(prev) || is_cjk(next)
+}
+
+struct LineBuilder<'a> {
+    metrics: &'a dyn FontMetrics,
+    spans: &'a [TextSpan],
+    /// (字符, 所属span)
+    chars: Vec<(char, usize)>,
+}
+
+impl<'a> LineBuilder<'a> {
+    /// 第i个字符增加的行宽, line_start为行首
+    fn advance(&self, line_start: usize, i: usize) -> f32 {
+        let (c, span) = self.chars[i];
+        let prev = if i > line_start && self.chars[i - 1].1 == span { Some(self.chars[i - 1].0) } else { None };
+        self.metrics.advance(prev, c, self.spans[span].size)
+    }
+
+    fn line(&self, chars: &[(char, usize)], empty_span: usize) -> LaidLine {
+        //去掉行尾空白
+        let end = chars.iter().rposition(|(c, _)| !c.is_whitespace()).map(|i| i + 1).unwrap_or(0);
+        let chars = &chars[..end];
+        let mut runs: Vec<LaidRun> = vec![];
+        let mut x = 0.;
+        let mut start = 0;
+        for i in 1..=chars.len() {
+            if i == chars.len() || chars[i].1 != chars[start].1 {
+                let span = &self.spans[chars[start].1];
+                let text: String = chars[start..i].iter().map(|(c, _)| c).collect();
+                let width = self.metrics.text_width(&text, span.size);
+                runs.push(LaidRun { text, color: span.color, size: span.size, x, width });
+                x += width;
+                start = i;
+            }
+        }
+        //行高和基线取本行最大字号
+        let sizes: Vec<u16> = if runs.is_empty() {
+            vec![self.spans.get(empty_span).map(|s| s.size).unwrap_or(0)]
+        } else {
+            runs.iter().map(|r| r.size).collect()
+        };
+        let height = sizes.iter().map(|s| self.metrics.line_height(*s)).fold(0., f32::max);
+        let baseline = sizes.iter().map(|s| self.metrics.baseline(*s)).fold(0., f32::max);
+        LaidLine { runs, x: 0., y: 0., width: x, height, baseline }
+    }
+}
+
+/// 文字布局. max_width为None时不自动换行, 遇到'\n'总是换行
+pub fn layout(spans: &[TextSpan], max_width: Option<f32>, align: Align, line_spacing: f32, metrics: &dyn FontMetrics) -> TextLayout {
+    let chars: Vec<(char, usize)> = spans.iter().enumerate()
+        .flat_map(|(i, span)| span.text.chars().map(move |c| (c, i)))
+        .collect();
+    let builder = LineBuilder { metrics, spans, chars };
+    let chars = &builder.chars;
+
+    let mut lines = vec![];
+    let mut line_start = 0;
+    //line_start..i的宽度
+    let mut line_width = 0.;
+    let mut i = 0;
+    while i <= chars.len() {
+        if i == chars.len() || chars[i].0 == '\n' {
+            let span = if i < chars.len() { chars[i].1 } else { chars.last().map(|c| c.1).unwrap_or(0) };
+            lines.push(builder.line(&chars[line_start..i], span));
+            i += 1;
+            line_start = i;
+            line_width = 0.;
+            continue;
+        }
+        let width = line_width + builder.advance(line_start, i);
+        if let Some(max_width) = max_width {
+            if width > max_width && !chars[i].0.is_whitespace() && i > line_start {
+                //回退到最近的可换行位置, 没有时在当前字符前强制换行
+                let break_at = (line_start + 1..=i).rev()
+                    .find(|&b| can_break(chars[b - 1].0, chars[b].0))
+                    .unwrap_or(i);
+                lines.push(builder.line(&chars[line_start..break_at], chars[break_at].1));
+                //跳过新行开头的空白
+                line_start = break_at;
+                while line_start < chars.len() && chars[line_start].0 == ' ' {
+                    line_start += 1;
+                }
+                i = line_start;
+                line_width = 0.;
+                continue;
+            }
+        }
+        line_width = width;
+        i += 1;
+    }
+
+    let width = match max_width {
+        Some(w) => w,
+        None => lines.iter().map(|l: &LaidLine| l.width).fold(0., f32::max),
+    };
+    let mut y = 0.;
+    for (n, line) in lines.iter_mut().enumerate() {
+        if n > 0 {
+            y += line_spacing;
+        }
+        line.y = y;
+        line.x = match align {
+            Align::Left => 0.,
+            Align::Center => (width - line.width) / 2.,
+            Align::Right => width - line.width,
+        };
+        y += line.height;
+    }
+    TextLayout { lines, width, height: y }
+}
+
+fn style_spans(text: &str, style: &TextStyle) -> Vec<TextSpan> {
+    if style.markup {
+        parse_markup(text, style.color, style.size)
+    } else {
+        vec![TextSpan { text: text.to_string(), color: style.color, size: style.size }]
+    }
+}
+
+/// 按样式布局文字, max_width为None时不换行
+pub fn layout_text(text: &str, max_width: Option<f32>, style: &TextStyle) -> TextLayout {
+    layout(&style_spans(text, style), max_width, style.align, style.line_spacing, &style.font)
+}
+
+/// 测量文字大小, max_width为None时不换行
+pub fn measure(text: &str, max_width: Option<f32>, style: &TextStyle) -> Vec2 {
+    let layout = layout_text(text, max_width, style);
+    vec2(layout.width, layout.height)
+}
+
+/// 在(x, y)绘制文字, (x, y)为文字左上角, 不自动换行
+pub fn draw_text(text: &str, x: f32, y: f32, style: &TextStyle) {
+    draw_layout(&layout_text(text, None, style), x, y, &style.font);
+}
+
+/// 在矩形内绘制文字, 超出宽度时自动换行
+pub fn draw_text_box(text: &str, rect: Rect, style: &TextStyle) {
+    draw_layout(&layout_text(text, Some(rect.w), style), rect.x, rect.y, &style.font);
+}
+
+/// 绘制文字时的缩放、旋转和着色
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextTransform {
+    pub scale: Vec2,
+    /// 旋转角度(弧度)
+    pub rotation: f32,
+    /// 旋转中心
+    pub pivot: Vec2,
+    /// 乘以文字颜色
+    pub tint: Color,
+}
+
+impl Default for TextTransform {
+    fn default() -> Self {
+        TextTransform { scale: Vec2::ONE, rotation: 0., pivot: Vec2::ZERO, tint: WHITE }
+    }
+}
+
+impl TextTransform {
+    fn rotate(&self, p: Vec2) -> Vec2 {
+        if self.rotation == 0. {
+            return p;
+        }
+        let (sin, cos) = self.rotation.sin_cos();
+        let d = p - self.pivot;
+        self.pivot + vec2(d.x * cos - d.y * sin, d.x * sin + d.y * cos)
+    }
+
+    fn color(&self, color: Color) -> Color {
+        Color::new(color.r * self.tint.r, color.g * self.tint.g, color.b * self.tint.b, color.a * self.tint.a)
+    }
+}
+
+/// 绘制布局好的文字
+pub fn draw_layout(layout: &TextLayout, x: f32, y: f32, font: &TextFont) {
+    draw_layout_ex(layout, x, y, font, &TextTransform::default());
+}
+
+/// 按变换绘制布局好的文字, (x, y)为缩放后的左上角, 旋转前
+pub fn draw_layout_ex(layout: &TextLayout, x: f32, y: f32, font: &TextFont, transform: &TextTransform) {
+    let scale = transform.scale;
+    for line in &layout.lines {
+        for run in &line.runs {
+            let start = vec2(x + (line.x + run.x) * scale.x, y + (line.y + line.baseline) * scale.y);
+            let color = transform.color(run.color);
+            match font {
+                TextFont::Bitmap(font) => font.draw_ex(&run.text, start, run.size, color, transform),
+                _ => {
+                    //macroquad绕起点旋转文字
+                    let start = transform.rotate(start);
+                    let mut params = TextParams {
+                        font_size: run.size,
+                        font_scale: scale.y,
+                        font_scale_aspect: if scale.y != 0. { scale.x / scale.y } else { 1. },
+                        rotation: transform.rotation,
+                        color,
+                        ..Default::default()
+                    };
+                    if let Some(font) = font.ttf() {
+                        params.font = font;
+                    }
+                    draw_text_ex(&run.text, start.x, start.y, params);
+                }
+            }
+        }
+    }
+}
+
+/// BMFont字符
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub source: Rect,
+    pub offset: Vec2,
+    pub advance: f32,
+    pub page: usize,
+}
+
+/// BMFont文本格式(.fnt)描述
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BitmapFontDesc {
+    /// 字体原始字号
+    pub size: f32,
+    pub line_height: f32,
+    pub base: f32,
+    /// 纹理文件名
+    pub pages: Vec<String>,
+    pub glyphs: HashMap<char, Glyph>,
+    pub kernings: HashMap<(char, char), f32>,
+}
+
+impl BitmapFontDesc {
+    /// 解析BMFont文本格式
+    pub fn parse(text: &str) -> Result<BitmapFontDesc> {
+        let mut desc = BitmapFontDesc::default();
+        for (n, line) in text.lines().enumerate() {
+            let mut parts = split_fnt_line(line).into_iter();
+            let tag = match parts.next() {
+                Some(tag) => tag,
+                None => continue,
+            };
+            let attrs: HashMap<String, String> = parts
+                .filter_map(|p| p.split_once('=').map(|(k, v)| (k.to_string(), v.trim_matches('"').to_string())))
+                .collect();
+            let num = |key: &str| -> Result<f32> {
+                attrs.get(key)
+                    .ok_or_else(|| anyhow!("第{}行缺少{}", n + 1, key))?
+                    .parse::<f32>()
+                    .map_err(|e| anyhow!("第{}行{}格式错误: {}", n + 1, key, e))
+            };
+            match tag.as_str() {
+                "info" => desc.size = num("size")?.abs(),
+                "common" => {
+                    desc.line_height = num("lineHeight")?;
+                    desc.base = num("base")?;
+                }
+                "page" => {
+                    let id = num("id")? as usize;
+                    let file = attrs.get("file").ok_or_else(|| anyhow!("第{}行缺少file", n + 1))?;
+                    if desc.pages.len() <= id {
+                        desc.pages.resize(id + 1, String::new());
+                    }
+                    desc.pages[id] = file.clone();
+                }
+                "char" => {
+                    let c = char::from_u32(num("id")? as u32).ok_or_else(|| anyhow!("第{}行字符id无效", n + 1))?;
+                    desc.glyphs.insert(c, Glyph {
+                        source: Rect::new(num("x")?, num("y")?, num("width")?, num("height")?),
+                        offset: vec2(num("xoffset")?, num("yoffset")?),
+                        advance: num("xadvance")?,
+                        page: num("page").unwrap_or(0.) as usize,
+                    });
+                }
+                "kerning" => {
+                    let first = char::from_u32(num("first")? as u32);
+                    let second = char::from_u32(num("second")? as u32);
+                    if let (Some(first), Some(second)) = (first, second) {
+                        desc.kernings.insert((first, second), num("amount")?);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if desc.size == 0. {
+            desc.size = desc.line_height;
+        }
+        Ok(desc)
+    }
+}
+
+/// 按空格拆分, 保留引号内的空格
+fn split_fnt_line(line: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// BMFont位图字体, 适合像素风格游戏
+#[derive(Clone, Debug)]
+pub struct BitmapFont {
+    pub desc: BitmapFontDesc,
+    pub pages: Vec<Texture2D>,
+}
+
+impl BitmapFont {
+    /// 加载.fnt文件和它引用的纹理(相对于.fnt所在目录)
+    pub async fn load(path: &str) -> Result<BitmapFont> {
+        let text = embed::load_string(path).await.with_context(|| format!("字体加载失败: {}", path))?;
+        let desc = BitmapFontDesc::parse(&text).with_context(|| format!("字体格式错误: {}", path))?;
+        let dir = path.rfind('/').map(|i| &path[..=i]).unwrap_or("");
+        let mut pages = vec![];
+        for page in &desc.pages {
+            let page_path = format!("{}{}", dir, page);
+            let texture = embed::load_texture(&page_path).await.with_context(|| format!("字体纹理加载失败: {}", page_path))?;
+            pages.push(texture);
+        }
+        Ok(BitmapFont { desc, pages })
+    }
+
+    fn scale(&self, size: u16) -> f32 {
+        if self.desc.size > 0. { size as f32 / self.desc.size } else { 1. }
+    }
+
+    pub fn text_width(&self, text: &str, size: u16) -> f32 {
+        let mut width = 0.;
+        let mut prev = None;
+        for c in text.chars() {
+            width += self.advance(prev, c, size);
+            prev = Some(c);
+        }
+        width
+    }
+
+    /// 字符宽度加上和前一个字符的字距调整
+    pub fn advance(&self, prev: Option<char>, c: char, size: u16) -> f32 {
+        let mut advance = self.desc.glyphs.get(&c).map(|g| g.advance).unwrap_or(0.);
+        if let Some(prev) = prev {
+            advance += self.desc.kernings.get(&(prev, c)).copied().unwrap_or(0.);
+        }
+        advance * self.scale(size)
+    }
+
+    pub fn line_height(&self, size: u16) -> f32 {
+        self.desc.line_height * self.scale(size)
+    }
+
+    pub fn baseline(&self, size: u16) -> f32 {
+        self.desc.base * self.scale(size)
+    }
+
+    /// 绘制一行文字, y为基线
+    pub fn draw(&self, text: &str, x: f32, y: f32, size: u16, color: Color) {
+        self.draw_ex(text, vec2(x, y), size, color, &TextTransform::default());
+    }
+
+    /// 按变换绘制一行文字, start为缩放后基线的起点, 旋转前
+    pub fn draw_ex(&self, text: &str, start: Vec2, size: u16, color: Color, transform: &TextTransform) {
+        let scale = transform.scale * self.scale(size);
+        let top = start.y - self.desc.base * scale.y;
+        let mut pen = start.x;
+        let mut prev = None;
+        for c in text.chars() {
+            if let Some(prev) = prev {
+                pen += self.desc.kernings.get(&(prev, c)).copied().unwrap_or(0.) * scale.x;
+            }
+            prev = Some(c);
+            let glyph = match self.desc.glyphs.get(&c) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            if let Some(texture) = self.pages.get(glyph.page) {
+                //每个字绕自己的中心旋转, 中心绕pivot旋转
+                let dest = Rect::new(pen + glyph.offset.x * scale.x, top + glyph.offset.y * scale.y, glyph.source.w * scale.x, glyph.source.h * scale.y);
+                let center = transform.rotate(dest.center());
+                draw_texture_ex(*texture, center.x - dest.w / 2., center.y - dest.h / 2., color, DrawTextureParams {
+                    source: Some(glyph.source),
+                    dest_size: Some(dest.size()),
+                    rotation: transform.rotation,
+                    ..Default::default()
+                });
+            }
+            pen += glyph.advance * scale.x;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// 等宽字体: 每个字符宽 size/2, 中日韩文字宽 size
+    struct Mono;
+
+    /// 记录测量过的字符数
+    struct Counted(Cell<usize>);
+
+    impl FontMetrics for Counted {
+        fn text_width(&self, text: &str, size: u16) -> f32 {
+            self.0.set(self.0.get() + text.chars().count());
+            Mono.text_width(text, size)
+        }
+        fn line_height(&self, size: u16) -> f32 {
+            Mono.line_height(size)
+        }
+        fn baseline(&self, size: u16) -> f32 {
+            Mono.baseline(size)
+        }
+    }
+
+    impl FontMetrics for Mono {
+        fn text_width(&self, text: &str, size: u16) -> f32 {
+            text.chars().map(|c| if is_cjk(c) { size as f32 } else { size as f32 / 2. }).sum()
+        }
+        fn line_height(&self, size: u16) -> f32 {
+            size as f32
+        }
+        fn baseline(&self, size: u16) -> f32 {
+            size as f32 * 0.8
+        }
+    }
+
+    fn lines(text: &str, width: f32) -> Vec<String> {
+        let spans = parse_markup(text, WHITE, 10);
+        layout(&spans, Some(width), Align::Left, 0., &Mono)
+            .lines
+            .iter()
+            .map(|l| l.runs.iter().map(|r| r.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_markup() {
+        let spans = parse_markup("a[color=#ff0000]b[size=20]c[/size][/color]d[[e[foo]", WHITE, 10);
+        assert_eq!(spans, vec![
+            TextSpan { text: "a".into(), color: WHITE, size: 10 },
+            TextSpan { text: "b".into(), color: Color::from_rgba(255, 0, 0, 255), size: 10 },
+            TextSpan { text: "c".into(), color: Color::from_rgba(255, 0, 0, 255), size: 20 },
+            TextSpan { text: "d[e[foo]".into(), color: WHITE, size: 10 },
+        ]);
+    }
+
+    #[test]
+    fn test_wrap() {
+        //每个字符宽5, 每行最多4个字符
+        assert_eq!(lines("ab cd efghij", 20.), vec!["ab", "cd", "efgh", "ij"]);
+        assert_eq!(lines("ab\n\ncd", 20.), vec!["ab", "", "cd"]);
+        //中文每字宽10, 标点不出现在行首
+        assert_eq!(lines("上下左右，键移动", 40.), vec!["上下左", "右，键移", "动"]);
+        assert_eq!(lines("按Enter键", 20.), vec!["按", "Ente", "r键"]);
+
+        //逐字累加行宽, 测量的字符数和文字长度成正比
+        let metrics = Counted(Cell::new(0));
+        let text = "abc ".repeat(1000);
+        let layout = layout(&[TextSpan { text, color: WHITE, size: 10 }], Some(1000.), Align::Left, 0., &metrics);
+        assert_eq!(layout.lines.len(), 20);
+        assert!(metrics.0.get() < 3 * 4000, "{}", metrics.0.get());
+    }
+
+    #[test]
+    fn test_align() {
+        let spans = parse_markup("ab\nabcd", WHITE, 10);
+        let layout = layout(&spans, Some(40.), Align::Right, 2., &Mono);
+        assert_eq!(layout.lines[0].x, 30.);
+        assert_eq!(layout.lines[1].x, 20.);
+        assert_eq!(layout.lines[1].y, 12.);
+        assert_eq!(layout.height, 22.);
+        let layout = super::layout(&spans, None, Align::Center, 0., &Mono);
+        assert_eq!(layout.width, 20.);
+        assert_eq!(layout.lines[0].x, 5.);
+    }
+
+    #[test]
+    fn test_bmfont() {
+        let desc = BitmapFontDesc::parse(r#"info face="Pixel Font" size=-8 bold=0
+common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1
+page id=0 file="pixel font.png"
+chars count=2
+char id=65 x=0 y=0 width=6 height=8 xoffset=0 yoffset=1 xadvance=7 page=0
+char id=66 x=6 y=0 width=6 height=8 xoffset=0 yoffset=1 xadvance=7 page=0
+kernings count=1
+kerning first=65 second=66 amount=-1"#).unwrap();
+        assert_eq!(desc.size, 8.);
+        assert_eq!(desc.pages, vec!["pixel font.png"]);
+        assert_eq!(desc.glyphs[&'B'].source, Rect::new(6., 0., 6., 8.));
+        let font = BitmapFont { desc, pages: vec![] };
+        assert_eq!(font.text_width("AB", 8), 13.);
+        assert_eq!(font.text_width("AB", 16), 26.);
+        assert_eq!(font.line_height(16), 20.);
+        assert!(BitmapFontDesc::parse("char id=65 x=0").is_err());
+    }
+}